@@ -0,0 +1,154 @@
+use crate::diff::{Change, ChangeKind, Diff, Item};
+use crate::Results;
+use anyhow::Result;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct ChangelogArgs {
+	/// Output JSON of the older schemas
+	old: String,
+
+	/// Output JSON of the newer schemas
+	new: String,
+
+	/// Template for the top heading; `{old}` and `{new}` are replaced with the compared files
+	#[arg(long, default_value = "Schema changes from {old} to {new}")]
+	heading: String,
+
+	/// Prefix for links to schema files, e.g. a repository's blob URL. Links are to the whole
+	/// file, since the output JSON doesn't record where in it each item is declared
+	#[arg(long, default_value = "")]
+	link_base: String,
+
+	/// Filepath for the changelog, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+impl ChangelogArgs {
+	pub fn run(self) -> Result<()> {
+		let old = Results::load(&self.old)?;
+		let new = Results::load(&self.new)?;
+		let diff = Diff::new(&old, &new);
+
+		let heading = self
+			.heading
+			.replace("{old}", &self.old)
+			.replace("{new}", &self.new);
+		let rendered = changelog(&diff, &heading, &self.link_base);
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => print!("{rendered}"),
+		}
+
+		Ok(())
+	}
+}
+
+/// Annotation values are JSON, but strings read better without their quotes.
+fn text(value: &serde_json::Value) -> String {
+	match value {
+		serde_json::Value::String(text) => text.clone(),
+		value => value.to_string(),
+	}
+}
+
+/// A Markdown link to the file declaring `change`, labelled with its name inside that file.
+fn link(change: &Change, link_base: &str) -> String {
+	let name = change
+		.name
+		.split_once(':')
+		.map_or(change.name.as_str(), |(_, name)| name);
+
+	format!("[`{name}`]({link_base}{})", change.file)
+}
+
+fn is_compat_flag(change: &Change) -> bool {
+	let flagged = change.annotations.contains_key("compatEnableFlag")
+		|| change.annotations.contains_key("compatDisableFlag");
+	let gained_flag = change
+		.details
+		.iter()
+		.any(|detail| detail.old.is_none() && detail.property == "$compatEnableFlag");
+
+	change.item == Item::Field
+		&& match change.change {
+			ChangeKind::Added => flagged,
+			ChangeKind::Changed => gained_flag,
+			ChangeKind::Removed => false,
+		}
+}
+
+/// Renders the changes release notes care about, grouped into sections. Empty sections are left out.
+pub fn changelog(diff: &Diff, heading: &str, link_base: &str) -> String {
+	let mut markdown = format!("# {heading}\n");
+	let mut section = |title: &str, lines: Vec<String>| {
+		if !lines.is_empty() {
+			let _ = writeln!(markdown, "\n## {title}\n");
+			for line in lines {
+				let _ = writeln!(markdown, "- {line}");
+			}
+		}
+	};
+
+	let flags = diff
+		.changes
+		.iter()
+		.filter(|change| is_compat_flag(change))
+		.map(|change| {
+			let annotation = |name: &str| change.annotations.get(name).map(text);
+
+			let mut notes = vec![];
+			if let Some(flag) = annotation("compatEnableFlag") {
+				notes.push(format!("enable with `{flag}`"));
+			}
+			if let Some(flag) = annotation("compatDisableFlag") {
+				notes.push(format!("disable with `{flag}`"));
+			}
+			if let Some(date) = annotation("compatEnableDate") {
+				notes.push(format!("default from {date}"));
+			}
+			if change.annotations.contains_key("experimental") {
+				notes.push("experimental".to_string());
+			}
+
+			format!("{}: {}", link(change, link_base), notes.join(", "))
+		})
+		.collect();
+	section("New compatibility flags", flags);
+
+	let fields = diff
+		.changes
+		.iter()
+		.filter(|change| change.item == Item::Field && change.change == ChangeKind::Added)
+		.filter(|change| !is_compat_flag(change))
+		.map(|change| link(change, link_base))
+		.collect();
+	section("New fields", fields);
+
+	let methods = diff
+		.changes
+		.iter()
+		.filter(|change| change.item == Item::Method && change.change == ChangeKind::Removed)
+		.map(|change| link(change, link_base))
+		.collect();
+	section("Removed methods", methods);
+
+	let annotations = diff
+		.changes
+		.iter()
+		.filter(|change| change.change == ChangeKind::Changed)
+		.flat_map(|change| {
+			change
+				.details
+				.iter()
+				.filter(|detail| detail.property.starts_with('$'))
+				.map(move |detail| format!("{}: {detail}", link(change, link_base)))
+		})
+		.collect();
+	section("Changed annotations", annotations);
+
+	markdown
+}
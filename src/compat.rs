@@ -0,0 +1,278 @@
+use crate::diff::{Change, ChangeKind, Detail, Diff, Item};
+use crate::Results;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct CompatArgs {
+	/// Output JSON of the older schemas
+	old: String,
+
+	/// Output JSON of the newer schemas
+	new: String,
+
+	/// Format of the emitted report
+	#[arg(short, long, value_enum, default_value_t = Format::Text)]
+	format: Format,
+
+	/// Filepath for the report, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+	/// One line per breaking change, with the reasons indented below it
+	Text,
+	/// The list of breaking changes
+	Json,
+}
+
+impl CompatArgs {
+	/// Fails when any change breaks wire or API compatibility, so the exit code can gate merges.
+	pub fn run(self) -> Result<()> {
+		let old = Results::load(&self.old)?;
+		let new = Results::load(&self.new)?;
+		let diff = Diff::new(&old, &new);
+		let report = Report::new(&diff);
+
+		let rendered = match self.format {
+			Format::Text => report.to_text(),
+			Format::Json => serde_json::to_string_pretty(&report)?,
+		};
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => print!("{rendered}"),
+		}
+
+		if !report.breaking.is_empty() {
+			bail!("{} breaking change(s) found", report.breaking.len());
+		}
+
+		Ok(())
+	}
+}
+
+/// A change that existing readers, writers or callers can't cope with.
+#[derive(Serialize)]
+pub struct Breaking<'a> {
+	#[serde(flatten)]
+	pub change: &'a Change,
+	pub reasons: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Report<'a> {
+	pub breaking: Vec<Breaking<'a>>,
+}
+
+impl<'a> Report<'a> {
+	pub fn new(diff: &'a Diff) -> Self {
+		let breaking = diff
+			.changes
+			.iter()
+			.filter_map(|change| {
+				let reasons = reasons(change);
+				(!reasons.is_empty()).then_some(Breaking { change, reasons })
+			})
+			.collect();
+
+		Report { breaking }
+	}
+
+	fn to_text(&self) -> String {
+		let mut text = String::new();
+
+		for breaking in &self.breaking {
+			let _ = writeln!(text, "{} {}", breaking.change.item, breaking.change.name);
+			for reason in &breaking.reasons {
+				let _ = writeln!(text, "    {reason}");
+			}
+		}
+
+		text
+	}
+}
+
+/// Why `change` is breaking, or nothing when it is safe.
+fn reasons(change: &Change) -> Vec<String> {
+	match change.change {
+		// new fields, enumerants and methods are exactly how capnp schemas are meant to evolve
+		ChangeKind::Added => vec![],
+		ChangeKind::Removed => vec![format!("{} removed", change.item)],
+		ChangeKind::Changed => change
+			.details
+			.iter()
+			.filter(|detail| breaks(change.item, detail))
+			.map(ToString::to_string)
+			.collect(),
+	}
+}
+
+/// Whether a change to a property of an `item` alters the wire layout or the generated API.
+fn breaks(item: Item, detail: &Detail) -> bool {
+	// nodes are matched by ID and members by ordinal, so changing either is a removal
+	match (item, detail.property.as_str()) {
+		(Item::Struct, "union") | (Item::Field, "group.union") => union_breaks(detail),
+		(Item::Struct, "parameters") => true,
+		(Item::Enum | Item::Interface, "parameters") => true,
+		// primitive fields are stored XORed with their default, so changing it changes every value
+		(Item::Field, "type" | "offset" | "default" | "discriminant_value" | "group" | "group.id") => true,
+		(Item::Method, "params" | "results" | "params.inline" | "results.inline" | "implicit_parameters") => {
+			true
+		}
+		(Item::Const | Item::Annotation, "type") => true,
+		_ => false,
+	}
+}
+
+/// A union can gain members, or be added to a struct that had none, but its discriminant
+/// has to stay put and every value already written has to stay valid.
+fn union_breaks(detail: &Detail) -> bool {
+	let field = |union: &Option<serde_json::Value>, name| {
+		union
+			.as_ref()
+			.map(|union| union[name].as_u64().unwrap_or_default())
+	};
+
+	match (&detail.old, &detail.new) {
+		(Some(_), Some(_)) => {
+			field(&detail.old, "discriminant_offset") != field(&detail.new, "discriminant_offset")
+				|| field(&detail.new, "discriminant_count") < field(&detail.old, "discriminant_count")
+		}
+		(None, _) => false,
+		(Some(_), None) => true,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::{json, Value};
+
+	/// A document holding just the struct `X`, with the given union and fields.
+	fn results(union: Value, fields: Value) -> Results {
+		let document = json!({
+			"files": [],
+			"structs": [{
+				"name": "x.capnp:X",
+				"id": "0xa000000000000001",
+				"file": "x.capnp",
+				"annotations": {},
+				"union": union,
+				"fields": fields,
+			}],
+			"enums": [],
+			"interfaces": [],
+			"consts": [],
+			"annotations": [],
+		});
+
+		serde_json::from_value(document).unwrap()
+	}
+
+	fn slot(name: &str, ordinal: u16, code_order: u16, kind: &str, default: Value) -> Value {
+		json!({
+			"name": name,
+			"type": { "kind": kind },
+			"ordinal": ordinal,
+			"code_order": code_order,
+			"offset": 0,
+			"default": default,
+			"annotations": {},
+		})
+	}
+
+	fn group(name: &str, id: &str, code_order: u16, fields: Value) -> Value {
+		json!({
+			"name": name,
+			"code_order": code_order,
+			"group": { "id": id, "fields": fields },
+			"annotations": {},
+		})
+	}
+
+	/// The names and reasons of every breaking change from `old` to `new`.
+	fn breaking(old: &Results, new: &Results) -> Vec<(String, Vec<String>)> {
+		let diff = Diff::new(old, new);
+
+		Report::new(&diff)
+			.breaking
+			.into_iter()
+			.map(|breaking| (breaking.change.name.clone(), breaking.reasons))
+			.collect()
+	}
+
+	#[test]
+	fn adding_a_field_above_a_group_is_safe() {
+		let inner = json!([slot("x", 1, 0, "Int8", json!(0))]);
+		let old = results(
+			Value::Null,
+			json!([
+				slot("a", 0, 0, "UInt32", json!(0)),
+				group("g", "0xa000000000000002", 1, inner.clone()),
+			]),
+		);
+		let new = results(
+			Value::Null,
+			json!([
+				slot("a", 0, 0, "UInt32", json!(0)),
+				slot("b", 2, 1, "Text", json!("")),
+				group("g", "0xa000000000000002", 2, inner),
+			]),
+		);
+
+		assert_eq!(breaking(&old, &new), vec![]);
+	}
+
+	#[test]
+	fn changing_a_default_breaks() {
+		let old = results(Value::Null, json!([slot("a", 0, 0, "UInt32", json!(7))]));
+		let new = results(Value::Null, json!([slot("a", 0, 0, "UInt32", json!(8))]));
+
+		assert_eq!(
+			breaking(&old, &new),
+			vec![("x.capnp:X.a".to_string(), vec!["default: 7 -> 8".to_string()])]
+		);
+	}
+
+	#[test]
+	fn shrinking_a_union_breaks_but_growing_it_is_safe() {
+		let union = |count| json!({ "discriminant_offset": 2, "discriminant_count": count });
+		let small = results(union(2), json!([]));
+		let large = results(union(3), json!([]));
+
+		assert_eq!(breaking(&small, &large), vec![]);
+		assert_eq!(breaking(&large, &small).len(), 1);
+		assert_eq!(breaking(&large, &small)[0].0, "x.capnp:X");
+	}
+
+	#[test]
+	fn removing_a_member_breaks() {
+		let old = results(
+			Value::Null,
+			json!([slot("a", 0, 0, "UInt32", json!(0)), slot("b", 1, 1, "Text", json!(""))]),
+		);
+		let new = results(Value::Null, json!([slot("a", 0, 0, "UInt32", json!(0))]));
+
+		assert_eq!(
+			breaking(&old, &new),
+			vec![("x.capnp:X.b".to_string(), vec!["field removed".to_string()])]
+		);
+	}
+
+	#[test]
+	fn changing_a_field_type_breaks() {
+		let old = results(Value::Null, json!([slot("a", 0, 0, "UInt32", json!(0))]));
+		let new = results(Value::Null, json!([slot("a", 0, 0, "Int32", json!(0))]));
+
+		assert_eq!(
+			breaking(&old, &new),
+			vec![("x.capnp:X.a".to_string(), vec!["type: UInt32 -> Int32".to_string()])]
+		);
+	}
+}
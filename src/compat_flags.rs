@@ -0,0 +1,253 @@
+use crate::{extract, Annotations, Field, Options, Results, SchemaArgs};
+use anyhow::Result;
+use capnpc::codegen::GeneratorContext;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct CompatFlagsArgs {
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	/// Read an existing output JSON file instead of compiling the schemas
+	#[arg(long)]
+	input: Option<String>,
+
+	/// Format of the emitted report
+	#[arg(short, long, value_enum, default_value_t = Format::Json)]
+	format: Format,
+
+	/// Filepath for the report, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+	Json,
+	Markdown,
+	Csv,
+}
+
+impl CompatFlagsArgs {
+	pub fn run(self) -> Result<()> {
+		let results = match &self.input {
+			Some(path) => Results::load(path)?,
+			None => {
+				let message = self.schemas.compile()?;
+				let gen = GeneratorContext::new(&message)?;
+				let options = Options {
+					include_imports: false,
+					flatten_inherited: false,
+					verbose: false,
+				};
+
+				extract(&gen, &options)?
+			}
+		};
+
+		let flags = flags(&results);
+		let rendered = match self.format {
+			Format::Json => serde_json::to_string_pretty(&flags)?,
+			Format::Markdown => to_markdown(&flags),
+			Format::Csv => to_csv(&flags),
+		};
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => println!("{rendered}"),
+		}
+
+		Ok(())
+	}
+}
+
+// the annotations workerd puts on the fields of `CompatibilityFlags`
+const ENABLE_FLAG: &str = "compatEnableFlag";
+const DISABLE_FLAG: &str = "compatDisableFlag";
+const ENABLE_DATE: &str = "compatEnableDate";
+const EXPERIMENTAL: &str = "experimental";
+const IMPLIED_BY_AFTER_DATE: &str = "impliedByAfterDate";
+
+/// Flags that switch on by default once another flag is enabled and a date has passed.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ImpliedBy {
+	pub names: Vec<String>,
+	pub date: String,
+}
+
+/// One compatibility flag, read from the annotations on the field that stores it.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Flag {
+	/// The struct declaring the field, e.g. `compatibility-date.capnp:CompatibilityFlags`.
+	pub r#struct: String,
+	/// The field's path below the struct, through any groups it is nested in.
+	pub field: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ordinal: Option<u16>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub enable_flag: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub disable_flag: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub enable_date: Option<String>,
+	pub experimental: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub implied_by: Option<ImpliedBy>,
+	/// Any other annotations on the field, such as `$neededByFl`.
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl Flag {
+	fn new(r#struct: &str, field: &str, ordinal: Option<u16>, annotations: &Annotations) -> Self {
+		let text = |name: &str| match annotations.get(name) {
+			Some(serde_json::Value::String(text)) => Some(text.clone()),
+			Some(value) => Some(value.to_string()),
+			None => None,
+		};
+
+		// older schemas name a single flag, newer ones a list
+		let implied_by = annotations.get(IMPLIED_BY_AFTER_DATE).map(|value| {
+			let names = match (value.get("names"), value.get("name")) {
+				(Some(serde_json::Value::Array(names)), _) => names
+					.iter()
+					.filter_map(|name| name.as_str().map(str::to_string))
+					.collect(),
+				(_, Some(serde_json::Value::String(name))) => vec![name.clone()],
+				_ => vec![],
+			};
+			let date = value
+				.get("date")
+				.and_then(|date| date.as_str())
+				.unwrap_or_default();
+
+			ImpliedBy {
+				names,
+				date: date.to_string(),
+			}
+		});
+
+		let known = [
+			ENABLE_FLAG,
+			DISABLE_FLAG,
+			ENABLE_DATE,
+			EXPERIMENTAL,
+			IMPLIED_BY_AFTER_DATE,
+		];
+		let other = annotations
+			.iter()
+			.filter(|(name, _)| !known.contains(&name.as_str()))
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect();
+
+		Flag {
+			r#struct: r#struct.to_string(),
+			field: field.to_string(),
+			ordinal,
+			enable_flag: text(ENABLE_FLAG),
+			disable_flag: text(DISABLE_FLAG),
+			enable_date: text(ENABLE_DATE),
+			experimental: annotations.contains_key(EXPERIMENTAL),
+			implied_by,
+			other,
+		}
+	}
+}
+
+/// Every field carrying an enable or disable flag, in declaration order.
+pub fn flags(results: &Results) -> Vec<Flag> {
+	let mut flags = vec![];
+
+	for node in &results.structs {
+		group_flags(&mut flags, &node.declaration.name, None, &node.fields);
+	}
+
+	flags
+}
+
+/// Adds the flags among `fields` and the fields of any groups below them.
+fn group_flags(flags: &mut Vec<Flag>, r#struct: &str, path: Option<&str>, fields: &[Field]) {
+	for field in fields {
+		let name = match path {
+			Some(path) => format!("{path}.{}", field.name),
+			None => field.name.clone(),
+		};
+
+		let annotations = &field.annotations;
+		if annotations.contains_key(ENABLE_FLAG) || annotations.contains_key(DISABLE_FLAG) {
+			flags.push(Flag::new(r#struct, &name, field.ordinal, annotations));
+		}
+
+		if let Some(group) = &field.group {
+			group_flags(flags, r#struct, Some(&name), &group.fields);
+		}
+	}
+}
+
+fn implied_by(flag: &Flag) -> String {
+	match &flag.implied_by {
+		Some(implied) => format!("{} after {}", implied.names.join(" or "), implied.date),
+		None => String::new(),
+	}
+}
+
+fn to_markdown(flags: &[Flag]) -> String {
+	let cell = |text: &str| text.replace('|', "\\|");
+	let code = |text: &Option<String>| match text {
+		Some(text) => format!("`{}`", cell(text)),
+		None => String::new(),
+	};
+
+	let mut markdown = String::from(
+		"| Struct | Field | Enable flag | Disable flag | Enable date | Experimental | Implied by |\n",
+	);
+	markdown.push_str("| --- | --- | --- | --- | --- | --- | --- |\n");
+
+	for flag in flags {
+		let _ = writeln!(
+			markdown,
+			"| `{}` | `{}` | {} | {} | {} | {} | {} |",
+			cell(&flag.r#struct),
+			cell(&flag.field),
+			code(&flag.enable_flag),
+			code(&flag.disable_flag),
+			flag.enable_date.as_deref().map(cell).unwrap_or_default(),
+			if flag.experimental { "yes" } else { "" },
+			cell(&implied_by(flag)),
+		);
+	}
+
+	markdown
+}
+
+fn to_csv(flags: &[Flag]) -> String {
+	let cell = |text: &str| match text.contains([',', '"', '\n']) {
+		true => format!("\"{}\"", text.replace('"', "\"\"")),
+		false => text.to_string(),
+	};
+
+	let mut csv =
+		String::from("struct,field,ordinal,enable_flag,disable_flag,enable_date,experimental,implied_by\n");
+	for flag in flags {
+		let columns = [
+			cell(&flag.r#struct),
+			cell(&flag.field),
+			flag.ordinal
+				.map(|ordinal| ordinal.to_string())
+				.unwrap_or_default(),
+			cell(flag.enable_flag.as_deref().unwrap_or_default()),
+			cell(flag.disable_flag.as_deref().unwrap_or_default()),
+			cell(flag.enable_date.as_deref().unwrap_or_default()),
+			flag.experimental.to_string(),
+			cell(&implied_by(flag)),
+		];
+
+		let _ = writeln!(csv, "{}", columns.join(","));
+	}
+
+	csv
+}
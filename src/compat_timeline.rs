@@ -0,0 +1,163 @@
+use crate::compat_flags::{flags, Flag};
+use crate::git::{self, Repo, Revision, RevisionArgs, Step};
+use crate::SchemaArgs;
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct CompatTimelineArgs {
+	#[command(flatten)]
+	revisions: RevisionArgs,
+
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	/// Format of the emitted timeline
+	#[arg(short, long, value_enum, default_value_t = Format::Json)]
+	format: Format,
+
+	/// Filepath for the timeline, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+	Json,
+	Markdown,
+}
+
+impl CompatTimelineArgs {
+	pub fn run(self) -> Result<()> {
+		let repo = Repo::open(&self.revisions.repo)?;
+		let mut timelines: Vec<Timeline> = vec![];
+
+		for step in git::walk(&repo, &self.revisions, &self.schemas, false)? {
+			let Step { revision, results, .. } = step?;
+			let flags = flags(&results);
+
+			for timeline in &mut timelines {
+				if timeline.removed.is_none() && !flags.iter().any(|flag| timeline.tracks(flag)) {
+					timeline.removed = Some(revision.clone());
+				}
+			}
+
+			for flag in flags {
+				match timelines.iter_mut().find(|timeline| timeline.tracks(&flag)) {
+					Some(timeline) => timeline.update(&revision, flag),
+					None => timelines.push(Timeline::new(&revision, flag)),
+				}
+			}
+		}
+
+		let rendered = match self.format {
+			Format::Json => serde_json::to_string_pretty(&timelines)?,
+			Format::Markdown => to_markdown(&timelines),
+		};
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => println!("{rendered}"),
+		}
+
+		Ok(())
+	}
+}
+
+/// When a compatibility flag reached each milestone. Flags in the first revision count as
+/// introduced there, since nothing before it was looked at.
+#[derive(Serialize)]
+pub struct Timeline {
+	pub name: String,
+	pub introduced: Revision,
+	/// The first revision where the flag had an enable date.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub enable_date_added: Option<Revision>,
+	/// The first revision where a previously `$experimental` flag no longer was.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub experimental_dropped: Option<Revision>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub removed: Option<Revision>,
+	/// The flag as of the last revision that had it.
+	pub latest: Flag,
+}
+
+impl Timeline {
+	fn new(revision: &Revision, flag: Flag) -> Self {
+		Timeline {
+			name: name(&flag).to_string(),
+			introduced: revision.clone(),
+			enable_date_added: flag.enable_date.as_ref().map(|_| revision.clone()),
+			experimental_dropped: None,
+			removed: None,
+			latest: flag,
+		}
+	}
+
+	/// Retired flags have their fields renamed to `obsoleteN`, but the ordinal stays, so that is
+	/// what identifies the flag. Only a field without one falls back to its name.
+	fn tracks(&self, flag: &Flag) -> bool {
+		let same_field = match (self.latest.ordinal, flag.ordinal) {
+			(Some(old), Some(new)) => old == new,
+			_ => self.latest.field == flag.field,
+		};
+
+		self.latest.r#struct == flag.r#struct && same_field
+	}
+
+	fn update(&mut self, revision: &Revision, flag: Flag) {
+		if self.enable_date_added.is_none() && flag.enable_date.is_some() {
+			self.enable_date_added = Some(revision.clone());
+		}
+		if self.experimental_dropped.is_none() && self.latest.experimental && !flag.experimental {
+			self.experimental_dropped = Some(revision.clone());
+		}
+
+		// a field that comes back is tracked as present again
+		self.removed = None;
+		self.name = name(&flag).to_string();
+		self.latest = flag;
+	}
+}
+
+/// The name people know a flag by.
+fn name(flag: &Flag) -> &str {
+	flag.enable_flag
+		.as_deref()
+		.or(flag.disable_flag.as_deref())
+		.unwrap_or(&flag.field)
+}
+
+fn to_markdown(timelines: &[Timeline]) -> String {
+	let show = |revision: Option<&Revision>| match revision {
+		Some(revision) => format!("{} ({})", revision.name, revision.date),
+		None => String::new(),
+	};
+
+	let mut markdown =
+		String::from("| Flag | Field | Introduced | Enable date added | Experimental dropped | Removed |\n");
+	markdown.push_str("| --- | --- | --- | --- | --- | --- |\n");
+
+	for timeline in timelines {
+		let enable_date = match (&timeline.enable_date_added, &timeline.latest.enable_date) {
+			(Some(_), Some(date)) => format!("{date}, in {}", show(timeline.enable_date_added.as_ref())),
+			_ => show(timeline.enable_date_added.as_ref()),
+		};
+
+		let _ = writeln!(
+			markdown,
+			"| `{}` | `{}` | {} | {} | {} | {} |",
+			timeline.name,
+			timeline.latest.field,
+			show(Some(&timeline.introduced)),
+			enable_date,
+			show(timeline.experimental_dropped.as_ref()),
+			show(timeline.removed.as_ref()),
+		);
+	}
+
+	markdown
+}
@@ -0,0 +1,373 @@
+//! Writes the translated nodes out as the `CodeGeneratorRequest` the capnp tool would send.
+
+use super::parser::Application;
+use super::translate::{Brand, Compiler, FieldKind, Kind, Lookup, Node, Params, TypeRef};
+use super::value::Value;
+use anyhow::Result;
+use capnp::message::{self, HeapAllocator};
+use capnp::struct_list;
+use capnpc::schema_capnp::*;
+
+/// `StreamResult` in `/capnp/stream.capnp`.
+const STREAM_RESULT: u64 = 0x995f9a3377c0b16e;
+
+impl<'a> Compiler<'a> {
+	pub fn request(&self) -> Result<message::Builder<HeapAllocator>> {
+		let mut message = message::Builder::new_default();
+		let mut request = message.init_root::<code_generator_request::Builder>();
+
+		let mut nodes = request.reborrow().init_nodes(self.nodes.len() as u32);
+		for (index, node) in self.nodes.iter().enumerate() {
+			self.write_node(nodes.reborrow().get(index as u32), index, node)?;
+		}
+
+		let mut source_info = request.reborrow().init_source_info(self.nodes.len() as u32);
+		for (index, node) in self.nodes.iter().enumerate() {
+			let mut info = source_info.reborrow().get(index as u32);
+			info.set_id(node.id);
+			if let Some(doc) = node.doc {
+				info.set_doc_comment(doc);
+			}
+
+			let docs = self.member_docs(node);
+			let mut members = info.init_members(docs.len() as u32);
+			for (i, doc) in docs.into_iter().enumerate() {
+				if let Some(doc) = doc {
+					members.reborrow().get(i as u32).set_doc_comment(doc);
+				}
+			}
+		}
+
+		let requested: Vec<_> = (0..self.files.len())
+			.filter(|file| self.files[*file].requested)
+			.collect();
+		let mut files = request.init_requested_files(requested.len() as u32);
+		for (i, file) in requested.into_iter().enumerate() {
+			let source = &self.files[file];
+			let mut builder = files.reborrow().get(i as u32);
+			builder.set_id(self.nodes[self.file_nodes[file]].id);
+			builder.set_filename(&source.name);
+
+			let mut imports = builder.init_imports(source.imports.len() as u32);
+			for (j, (name, imported)) in source.imports.iter().enumerate() {
+				let mut import = imports.reborrow().get(j as u32);
+				import.set_id(self.nodes[self.file_nodes[*imported]].id);
+				import.set_name(name);
+			}
+		}
+
+		Ok(message)
+	}
+
+	/// Doc comments for a node's fields, enumerants or methods, in the order they are listed.
+	fn member_docs(&self, node: &Node<'a>) -> Vec<Option<&'a str>> {
+		match &node.kind {
+			Kind::Struct(def) => def.fields.iter().map(|field| field.doc).collect(),
+			Kind::Enum(enumerants) => enumerants.iter().map(|(_, decl)| decl.doc.as_deref()).collect(),
+			Kind::Interface { methods, .. } => {
+				methods.iter().map(|method| method.decl.doc.as_deref()).collect()
+			}
+			_ => vec![],
+		}
+	}
+
+	fn write_node(&self, mut builder: node::Builder, index: usize, node: &Node<'a>) -> Result<()> {
+		let lookup = Lookup::new(index);
+
+		builder.set_id(node.id);
+		builder.set_display_name(&node.display_name);
+		builder.set_display_name_prefix_length(node.prefix_len as u32);
+		builder.set_scope_id(node.scope_id);
+		builder.set_is_generic(node.is_generic);
+
+		let mut params = builder.reborrow().init_parameters(node.params.len() as u32);
+		for (i, param) in node.params.iter().enumerate() {
+			params.reborrow().get(i as u32).set_name(param);
+		}
+
+		let mut nested = builder.reborrow().init_nested_nodes(node.nested.len() as u32);
+		for (i, child) in node.nested.iter().enumerate() {
+			let child = &self.nodes[*child];
+			let mut entry = nested.reborrow().get(i as u32);
+			entry.set_name(&child.display_name[child.prefix_len..]);
+			entry.set_id(child.id);
+		}
+
+		let annotations = builder.reborrow().init_annotations(node.annotations.len() as u32);
+		self.write_annotations(annotations, node.annotations, lookup)?;
+
+		match &node.kind {
+			Kind::File => builder.set_file(()),
+			Kind::Struct(def) => {
+				let mut struct_ = builder.init_struct();
+				struct_.set_data_word_count(def.data_words);
+				struct_.set_pointer_count(def.pointers);
+				struct_.set_preferred_list_encoding(ElementSize::InlineComposite);
+				struct_.set_is_group(def.is_group);
+				struct_.set_discriminant_count(def.discriminant_count);
+				struct_.set_discriminant_offset(def.discriminant_offset);
+
+				let mut fields = struct_.init_fields(def.fields.len() as u32);
+				for (i, field) in def.fields.iter().enumerate() {
+					let mut builder = fields.reborrow().get(i as u32);
+					builder.set_name(field.name);
+					builder.set_code_order(field.code_order);
+					if let Some(discriminant) = field.discriminant {
+						builder.set_discriminant_value(discriminant);
+					}
+					match field.ordinal {
+						Some(ordinal) => builder.reborrow().init_ordinal().set_explicit(ordinal),
+						None => builder.reborrow().init_ordinal().set_implicit(()),
+					}
+
+					let annotations = builder
+						.reborrow()
+						.init_annotations(field.annotations.len() as u32);
+					self.write_annotations(annotations, field.annotations, def.lookup)?;
+
+					match &field.kind {
+						FieldKind::Slot {
+							type_,
+							offset,
+							default,
+						} => {
+							let mut slot = builder.init_slot();
+							slot.set_offset(*offset);
+							self.write_type(slot.reborrow().init_type(), type_)?;
+
+							let value = match default {
+								Some(default) => self.eval(default, type_, def.lookup)?,
+								None => Value::zero(type_),
+							};
+							self.write_value(slot.reborrow().init_default_value(), type_, &value)?;
+							slot.set_had_explicit_default(default.is_some());
+						}
+						FieldKind::Group(id) => builder.init_group().set_type_id(*id),
+					}
+				}
+			}
+			Kind::Enum(enumerants) => {
+				let mut list = builder.init_enum().init_enumerants(enumerants.len() as u32);
+				for (i, (code_order, decl)) in enumerants.iter().enumerate() {
+					let mut enumerant = list.reborrow().get(i as u32);
+					enumerant.set_name(&decl.name);
+					enumerant.set_code_order(*code_order);
+
+					let annotations = enumerant.init_annotations(decl.annotations.len() as u32);
+					self.write_annotations(annotations, &decl.annotations, lookup)?;
+				}
+			}
+			Kind::Interface { extends, methods } => {
+				let mut interface = builder.init_interface();
+
+				let mut list = interface.reborrow().init_methods(methods.len() as u32);
+				for (i, method) in methods.iter().enumerate() {
+					let mut builder = list.reborrow().get(i as u32);
+					builder.set_name(&method.decl.name);
+					builder.set_code_order(method.code_order);
+
+					let mut implicit = builder
+						.reborrow()
+						.init_implicit_parameters(method.implicit.len() as u32);
+					for (j, param) in method.implicit.iter().enumerate() {
+						implicit.reborrow().get(j as u32).set_name(param);
+					}
+
+					let (id, brand) = self.params_type(index, &method.params, method.implicit, lookup)?;
+					builder.set_param_struct_type(id);
+					self.write_brand(builder.reborrow().init_param_brand(), &brand)?;
+
+					let (id, brand) = self.params_type(index, &method.results, method.implicit, lookup)?;
+					builder.set_result_struct_type(id);
+					self.write_brand(builder.reborrow().init_result_brand(), &brand)?;
+
+					let annotations = builder.init_annotations(method.decl.annotations.len() as u32);
+					self.write_annotations(annotations, &method.decl.annotations, lookup)?;
+				}
+
+				let mut superclasses = interface.init_superclasses(extends.len() as u32);
+				for (i, expr) in extends.iter().enumerate() {
+					let TypeRef::Interface(id, brand) = self.type_of(expr, lookup)? else {
+						return Err(self.error(index, expr.pos, "only interfaces can be extended"));
+					};
+					let mut superclass = superclasses.reborrow().get(i as u32);
+					superclass.set_id(id);
+					self.write_brand(superclass.init_brand(), &brand)?;
+				}
+			}
+			Kind::Const { type_, value } => {
+				let type_ = self.type_of(type_, lookup)?;
+				let value = self.eval(value, &type_, lookup)?;
+
+				let mut const_ = builder.init_const();
+				self.write_type(const_.reborrow().init_type(), &type_)?;
+				self.write_value(const_.init_value(), &type_, &value)?;
+			}
+			Kind::Annotation { targets, type_ } => {
+				let type_ = self.type_of(type_, lookup)?;
+
+				let mut annotation = builder.init_annotation();
+				self.write_type(annotation.reborrow().init_type(), &type_)?;
+
+				let all = targets.iter().any(|target| target == "*");
+				let targets = |name: &str| all || targets.iter().any(|target| target == name);
+				annotation.set_targets_file(targets("file"));
+				annotation.set_targets_const(targets("const"));
+				annotation.set_targets_enum(targets("enum"));
+				annotation.set_targets_enumerant(targets("enumerant"));
+				annotation.set_targets_struct(targets("struct"));
+				annotation.set_targets_field(targets("field"));
+				annotation.set_targets_union(targets("union"));
+				annotation.set_targets_group(targets("group"));
+				annotation.set_targets_interface(targets("interface"));
+				annotation.set_targets_method(targets("method"));
+				annotation.set_targets_param(targets("param"));
+				annotation.set_targets_annotation(targets("annotation"));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The struct a method takes or returns, and the brand it is used with.
+	fn params_type(
+		&self,
+		interface: usize,
+		params: &Params<'a>,
+		implicit: &[String],
+		lookup: Lookup<'a>,
+	) -> Result<(u64, Brand)> {
+		match params {
+			Params::Type(expr) => match self.type_of(expr, lookup)? {
+				TypeRef::Struct(id, brand) => Ok((id, brand)),
+				_ => Err(self.error(interface, expr.pos, "a method's params must be a struct")),
+			},
+			Params::Struct(id) => {
+				// an inline param list sees the interface's parameters, and binds its own
+				// implicit ones to the method's
+				let mut brand = self.brand(interface, &[], lookup);
+				if !implicit.is_empty() {
+					let bound = (0..implicit.len() as u16).map(TypeRef::ImplicitParam).collect();
+					brand.insert(0, (*id, Some(bound)));
+				}
+				Ok((*id, brand))
+			}
+			Params::Stream(pos) => match self.node_by_id(STREAM_RESULT) {
+				Some(_) => Ok((STREAM_RESULT, vec![])),
+				None => Err(self.error(
+					interface,
+					*pos,
+					"/capnp/stream.capnp has no StreamResult, which '-> stream' returns",
+				)),
+			},
+		}
+	}
+
+	fn write_annotations(
+		&self,
+		mut builder: struct_list::Builder<annotation::Owned>,
+		annotations: &[Application],
+		lookup: Lookup<'a>,
+	) -> Result<()> {
+		for (i, application) in annotations.iter().enumerate() {
+			let index = self.node_of(&application.name, lookup)?;
+			let Kind::Annotation { type_, .. } = self.nodes[index].kind else {
+				return Err(self.error(
+					lookup.node,
+					application.pos,
+					format!("'{}' is not an annotation", self.nodes[index].display_name),
+				));
+			};
+
+			let type_ = self.type_of(type_, Lookup::new(index))?;
+			let value = match &application.value {
+				Some(expr) => self.eval(expr, &type_, lookup)?,
+				None if type_ == TypeRef::Void => Value::Void,
+				None => {
+					return Err(self.error(
+						lookup.node,
+						application.pos,
+						format!("'{}' needs a value", self.nodes[index].display_name),
+					));
+				}
+			};
+
+			let mut annotation = builder.reborrow().get(i as u32);
+			annotation.set_id(self.nodes[index].id);
+			self.write_value(annotation.init_value(), &type_, &value)?;
+		}
+
+		Ok(())
+	}
+
+	fn write_type(&self, mut builder: type_::Builder, type_: &TypeRef) -> Result<()> {
+		match type_ {
+			TypeRef::Void => builder.set_void(()),
+			TypeRef::Bool => builder.set_bool(()),
+			TypeRef::Int8 => builder.set_int8(()),
+			TypeRef::Int16 => builder.set_int16(()),
+			TypeRef::Int32 => builder.set_int32(()),
+			TypeRef::Int64 => builder.set_int64(()),
+			TypeRef::Uint8 => builder.set_uint8(()),
+			TypeRef::Uint16 => builder.set_uint16(()),
+			TypeRef::Uint32 => builder.set_uint32(()),
+			TypeRef::Uint64 => builder.set_uint64(()),
+			TypeRef::Float32 => builder.set_float32(()),
+			TypeRef::Float64 => builder.set_float64(()),
+			TypeRef::Text => builder.set_text(()),
+			TypeRef::Data => builder.set_data(()),
+			TypeRef::List(element) => self.write_type(builder.init_list().init_element_type(), element)?,
+			TypeRef::Enum(id, brand) => {
+				let mut enum_ = builder.init_enum();
+				enum_.set_type_id(*id);
+				self.write_brand(enum_.init_brand(), brand)?;
+			}
+			TypeRef::Struct(id, brand) => {
+				let mut struct_ = builder.init_struct();
+				struct_.set_type_id(*id);
+				self.write_brand(struct_.init_brand(), brand)?;
+			}
+			TypeRef::Interface(id, brand) => {
+				let mut interface = builder.init_interface();
+				interface.set_type_id(*id);
+				self.write_brand(interface.init_brand(), brand)?;
+			}
+			TypeRef::AnyPointer => builder.init_any_pointer().init_unconstrained().set_any_kind(()),
+			TypeRef::AnyStruct => builder.init_any_pointer().init_unconstrained().set_struct(()),
+			TypeRef::AnyList => builder.init_any_pointer().init_unconstrained().set_list(()),
+			TypeRef::Capability => builder.init_any_pointer().init_unconstrained().set_capability(()),
+			TypeRef::Param { scope_id, index } => {
+				let mut parameter = builder.init_any_pointer().init_parameter();
+				parameter.set_scope_id(*scope_id);
+				parameter.set_parameter_index(*index);
+			}
+			TypeRef::ImplicitParam(index) => builder
+				.init_any_pointer()
+				.init_implicit_method_parameter()
+				.set_parameter_index(*index),
+		}
+
+		Ok(())
+	}
+
+	fn write_brand(&self, builder: brand::Builder, brand: &Brand) -> Result<()> {
+		let mut scopes = builder.init_scopes(brand.len() as u32);
+
+		for (i, (scope_id, bindings)) in brand.iter().enumerate() {
+			let mut scope = scopes.reborrow().get(i as u32);
+			scope.set_scope_id(*scope_id);
+
+			match bindings {
+				None => scope.set_inherit(()),
+				Some(types) => {
+					let mut list = scope.init_bind(types.len() as u32);
+					for (j, type_) in types.iter().enumerate() {
+						self.write_type(list.reborrow().get(j as u32).init_type(), type_)?;
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,447 @@
+//! Assigns data and pointer offsets to struct fields with the same algorithm capnp uses, so the
+//! offsets agree with the ones the capnp tool would have picked.
+//!
+//! Sizes are log2 of the size in bits: a bool is 0, a 16-bit value 4 and a word 6.
+
+/// Unused padding in a data section: at most one hole of each size below a word, stored as
+/// the hole's offset in multiples of its size. No hole can sit at offset zero, so zero means
+/// there is none.
+#[derive(Default, Clone, Copy)]
+struct HoleSet {
+	holes: [u32; 6],
+}
+
+impl HoleSet {
+	fn try_allocate(&mut self, lg_size: u32) -> Option<u32> {
+		let size = lg_size as usize;
+		if size >= self.holes.len() {
+			None
+		} else if self.holes[size] != 0 {
+			Some(std::mem::take(&mut self.holes[size]))
+		} else {
+			// split the next larger hole, keeping its second half as a hole of this size
+			let next = self.try_allocate(lg_size + 1)?;
+			let offset = next * 2;
+			self.holes[size] = offset + 1;
+			Some(offset)
+		}
+	}
+
+	/// Records the holes left over after taking a `lg_size` field at the start of a fresh
+	/// `limit`-sized space, where `offset` is the slot just past the field.
+	fn add_holes_at_end(&mut self, mut lg_size: u32, mut offset: u32, limit: u32) {
+		while lg_size < limit {
+			self.holes[lg_size as usize] = offset;
+			lg_size += 1;
+			offset = offset.div_ceil(2);
+		}
+	}
+
+	/// Grows the value at `old_offset` by merging it with the holes right after it.
+	fn try_expand(&mut self, old_lg_size: u32, old_offset: u32, expansion: u32) -> bool {
+		if expansion == 0 {
+			return true;
+		}
+		let size = old_lg_size as usize;
+		if size >= self.holes.len() || self.holes[size] != old_offset + 1 {
+			return false;
+		}
+
+		if self.try_expand(old_lg_size + 1, old_offset >> 1, expansion - 1) {
+			self.holes[size] = 0;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn smallest_at_least(&self, lg_size: u32) -> Option<u32> {
+		(lg_size..self.holes.len() as u32).find(|size| self.holes[*size as usize] != 0)
+	}
+}
+
+/// Somewhere fields can be added: the struct itself, or one member of a union.
+#[derive(Clone, Copy, Debug)]
+pub enum Container {
+	Top,
+	Group(usize),
+}
+
+#[derive(Clone, Copy)]
+struct DataLocation {
+	lg_size: u32,
+	/// In multiples of the location's size.
+	offset: u32,
+}
+
+struct Union {
+	parent: Container,
+	group_count: u32,
+	discriminant_offset: Option<u32>,
+	/// Space shared by the union's members.
+	data_locations: Vec<DataLocation>,
+	pointer_locations: Vec<u32>,
+}
+
+/// How much of one of its union's data locations a group has used.
+#[derive(Default, Clone, Copy)]
+struct Usage {
+	used: bool,
+	lg_size_used: u32,
+	/// Relative to the start of the location.
+	holes: HoleSet,
+}
+
+struct Group {
+	union: usize,
+	usage: Vec<Usage>,
+	pointers_used: usize,
+	has_members: bool,
+}
+
+#[derive(Default)]
+pub struct Layout {
+	pub data_words: u32,
+	pub pointers: u32,
+	holes: HoleSet,
+	unions: Vec<Union>,
+	groups: Vec<Group>,
+}
+
+impl Layout {
+	pub fn new_union(&mut self, parent: Container) -> usize {
+		self.unions.push(Union {
+			parent,
+			group_count: 0,
+			discriminant_offset: None,
+			data_locations: vec![],
+			pointer_locations: vec![],
+		});
+		self.unions.len() - 1
+	}
+
+	/// A member of `union`, which gets its own group even when it is a single field.
+	pub fn new_group(&mut self, union: usize) -> Container {
+		self.groups.push(Group {
+			union,
+			usage: vec![],
+			pointers_used: 0,
+			has_members: false,
+		});
+		Container::Group(self.groups.len() - 1)
+	}
+
+	pub fn discriminant_offset(&self, union: usize) -> Option<u32> {
+		self.unions[union].discriminant_offset
+	}
+
+	/// Allocates the union's discriminant, unless it already has one.
+	pub fn add_discriminant(&mut self, union: usize) -> bool {
+		if self.unions[union].discriminant_offset.is_some() {
+			return false;
+		}
+
+		let offset = self.add_data(self.unions[union].parent, 4);
+		self.unions[union].discriminant_offset = Some(offset);
+		true
+	}
+
+	pub fn add_void(&mut self, container: Container) {
+		if let Container::Group(group) = container {
+			self.add_member(group);
+
+			// a union nested in another union's member still counts as a member being added, so
+			// the outer union's discriminant is placed at the same point capnp places it
+			let parent = self.unions[self.groups[group].union].parent;
+			self.add_void(parent);
+		}
+	}
+
+	/// Returns the field's offset in multiples of its size.
+	pub fn add_data(&mut self, container: Container, lg_size: u32) -> u32 {
+		match container {
+			Container::Top => match self.holes.try_allocate(lg_size) {
+				Some(offset) => offset,
+				None => {
+					let offset = self.data_words << (6 - lg_size);
+					self.data_words += 1;
+					self.holes.add_holes_at_end(lg_size, offset + 1, 6);
+					offset
+				}
+			},
+			Container::Group(group) => self.group_add_data(group, lg_size),
+		}
+	}
+
+	pub fn add_pointer(&mut self, container: Container) -> u32 {
+		match container {
+			Container::Top => {
+				self.pointers += 1;
+				self.pointers - 1
+			}
+			Container::Group(group) => {
+				self.add_member(group);
+
+				let union = self.groups[group].union;
+				let used = self.groups[group].pointers_used;
+				self.groups[group].pointers_used += 1;
+
+				match self.unions[union].pointer_locations.get(used) {
+					Some(offset) => *offset,
+					None => {
+						let offset = self.add_pointer(self.unions[union].parent);
+						self.unions[union].pointer_locations.push(offset);
+						offset
+					}
+				}
+			}
+		}
+	}
+
+	fn try_expand_data(
+		&mut self,
+		container: Container,
+		old_lg_size: u32,
+		old_offset: u32,
+		expansion: u32,
+	) -> bool {
+		let group = match container {
+			Container::Top => return self.holes.try_expand(old_lg_size, old_offset, expansion),
+			Container::Group(group) => group,
+		};
+
+		if old_lg_size + expansion > 6 || old_offset & ((1 << expansion) - 1) != 0 {
+			return false;
+		}
+
+		let union = self.groups[group].union;
+		for i in 0..self.groups[group].usage.len() {
+			let location = self.unions[union].data_locations[i];
+			if location.lg_size < old_lg_size
+				|| old_offset >> (location.lg_size - old_lg_size) != location.offset
+			{
+				continue;
+			}
+
+			let local_offset = old_offset - (location.offset << (location.lg_size - old_lg_size));
+			let usage = self.groups[group].usage[i];
+			return if local_offset == 0 && usage.lg_size_used == old_lg_size {
+				// the value is all this group uses of the location, so the usage grows with it
+				self.try_expand_usage(group, i, old_lg_size + expansion, false)
+			} else {
+				self.groups[group].usage[i]
+					.holes
+					.try_expand(old_lg_size, local_offset, expansion)
+			};
+		}
+
+		false
+	}
+
+	fn add_member(&mut self, group: usize) {
+		if self.groups[group].has_members {
+			return;
+		}
+		self.groups[group].has_members = true;
+
+		// a union needs its discriminant once a second member has something in it
+		let union = self.groups[group].union;
+		self.unions[union].group_count += 1;
+		if self.unions[union].group_count == 2 {
+			self.add_discriminant(union);
+		}
+	}
+
+	fn group_add_data(&mut self, group: usize, lg_size: u32) -> u32 {
+		self.add_member(group);
+
+		let union = self.groups[group].union;
+		let locations = self.unions[union].data_locations.len();
+		self.groups[group].usage.resize(locations, Usage::default());
+
+		// the smallest hole the field fits in keeps fragmentation down
+		let mut best: Option<(u32, usize)> = None;
+		for i in 0..locations {
+			let location = self.unions[union].data_locations[i];
+			let usage = self.groups[group].usage[i];
+			if let Some(hole) = smallest_hole_at_least(&usage, location, lg_size) {
+				if best.is_none_or(|(size, _)| hole < size) {
+					best = Some((hole, i));
+				}
+			}
+		}
+		if let Some((_, i)) = best {
+			return self.allocate_from_hole(group, i, lg_size);
+		}
+
+		for i in 0..locations {
+			if let Some(offset) = self.try_allocate_by_expanding(group, i, lg_size) {
+				return offset;
+			}
+		}
+
+		let offset = self.add_data(self.unions[union].parent, lg_size);
+		self.unions[union]
+			.data_locations
+			.push(DataLocation { lg_size, offset });
+		self.groups[group].usage.push(Usage {
+			used: true,
+			lg_size_used: lg_size,
+			holes: HoleSet::default(),
+		});
+		offset
+	}
+
+	fn allocate_from_hole(&mut self, group: usize, i: usize, lg_size: u32) -> u32 {
+		let union = self.groups[group].union;
+		let location = self.unions[union].data_locations[i];
+		let base = location.offset << (location.lg_size - lg_size);
+		let usage = &mut self.groups[group].usage[i];
+
+		if !usage.used {
+			usage.used = true;
+			usage.lg_size_used = lg_size;
+			base
+		} else if lg_size >= usage.lg_size_used {
+			// pad out to the field's size, then put it right after what's used so far
+			usage.holes.add_holes_at_end(usage.lg_size_used, 1, lg_size);
+			usage.lg_size_used = lg_size + 1;
+			base + 1
+		} else if let Some(hole) = usage.holes.try_allocate(lg_size) {
+			base + hole
+		} else {
+			// double the space used and take the start of the new half
+			let offset = 1 << (usage.lg_size_used - lg_size);
+			usage
+				.holes
+				.add_holes_at_end(lg_size, offset + 1, usage.lg_size_used);
+			usage.lg_size_used += 1;
+			base + offset
+		}
+	}
+
+	fn try_allocate_by_expanding(&mut self, group: usize, i: usize, lg_size: u32) -> Option<u32> {
+		let union = self.groups[group].union;
+		let usage = self.groups[group].usage[i];
+
+		if !usage.used {
+			if !self.try_expand_location(union, i, lg_size) {
+				return None;
+			}
+
+			let location = self.unions[union].data_locations[i];
+			let usage = &mut self.groups[group].usage[i];
+			usage.used = true;
+			usage.lg_size_used = lg_size;
+			return Some(location.offset << (location.lg_size - lg_size));
+		}
+
+		let new_size = usage.lg_size_used.max(lg_size) + 1;
+		if !self.try_expand_usage(group, i, new_size, true) {
+			return None;
+		}
+
+		let location = self.unions[union].data_locations[i];
+		let hole = self.groups[group].usage[i].holes.try_allocate(lg_size)?;
+		Some((location.offset << (location.lg_size - lg_size)) + hole)
+	}
+
+	fn try_expand_usage(&mut self, group: usize, i: usize, desired: u32, new_holes: bool) -> bool {
+		let union = self.groups[group].union;
+		if desired > self.unions[union].data_locations[i].lg_size
+			&& !self.try_expand_location(union, i, desired)
+		{
+			return false;
+		}
+
+		let usage = &mut self.groups[group].usage[i];
+		if new_holes {
+			usage.holes.add_holes_at_end(usage.lg_size_used, 1, desired);
+		}
+		usage.lg_size_used = desired;
+		true
+	}
+
+	fn try_expand_location(&mut self, union: usize, i: usize, lg_size: u32) -> bool {
+		let location = self.unions[union].data_locations[i];
+		if lg_size <= location.lg_size {
+			return true;
+		}
+
+		let expansion = lg_size - location.lg_size;
+		if !self.try_expand_data(
+			self.unions[union].parent,
+			location.lg_size,
+			location.offset,
+			expansion,
+		) {
+			return false;
+		}
+
+		self.unions[union].data_locations[i] = DataLocation {
+			lg_size,
+			offset: location.offset >> expansion,
+		};
+		true
+	}
+}
+
+fn smallest_hole_at_least(usage: &Usage, location: DataLocation, lg_size: u32) -> Option<u32> {
+	if !usage.used {
+		// the whole location is one hole
+		(lg_size <= location.lg_size).then_some(location.lg_size)
+	} else if lg_size >= usage.lg_size_used {
+		(lg_size < location.lg_size).then_some(lg_size)
+	} else if let Some(hole) = usage.holes.smallest_at_least(lg_size) {
+		Some(hole)
+	} else {
+		(usage.lg_size_used < location.lg_size).then_some(usage.lg_size_used)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fields_fill_the_holes_left_by_smaller_ones() {
+		let mut layout = Layout::default();
+
+		assert_eq!(layout.add_data(Container::Top, 0), 0);
+		assert_eq!(layout.add_data(Container::Top, 6), 1);
+		// the rest of the first word is split into one hole of each size
+		assert_eq!(layout.add_data(Container::Top, 3), 1);
+		assert_eq!(layout.add_data(Container::Top, 4), 1);
+		assert_eq!(layout.add_data(Container::Top, 5), 1);
+		assert_eq!(layout.add_data(Container::Top, 0), 1);
+		assert_eq!(layout.data_words, 2);
+
+		// nothing left for a byte, so it starts a third word
+		assert_eq!(layout.add_data(Container::Top, 3), 16);
+		assert_eq!(layout.add_data(Container::Top, 4), 9);
+		assert_eq!(layout.data_words, 3);
+	}
+
+	#[test]
+	fn union_members_share_and_expand_locations() {
+		// the start of schema.capnp's `Value`, whose offsets come from capnp's generated code
+		let mut layout = Layout::default();
+		let union = layout.new_union(Container::Top);
+		let mut member = || layout.new_group(union);
+		let members: Vec<Container> = (0..8).map(|_| member()).collect();
+
+		layout.add_void(members[0]);
+		// the second member brings in the discriminant, ahead of its own field
+		assert_eq!(layout.add_data(members[1], 0), 16);
+		assert_eq!(layout.discriminant_offset(union), Some(0));
+		assert_eq!(layout.add_data(members[2], 3), 2);
+		assert_eq!(layout.add_data(members[3], 4), 1);
+		assert_eq!(layout.add_data(members[4], 5), 1);
+		assert_eq!(layout.add_data(members[5], 6), 1);
+		assert_eq!(layout.add_data(members[6], 3), 2);
+		assert_eq!(layout.add_pointer(members[7]), 0);
+
+		assert_eq!(layout.data_words, 2);
+		assert_eq!(layout.pointers, 1);
+	}
+}
@@ -0,0 +1,444 @@
+//! Splits schema source into statements the way capnp's lexer does: a run of tokens ended by
+//! either `;` or a `{ ... }` block, with the doc comment that directly follows the `;` or `{`.
+
+use super::Error;
+
+#[derive(Debug, Clone)]
+pub struct Token {
+	pub kind: TokenKind,
+	/// Byte offset into the source, for error messages.
+	pub pos: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum TokenKind {
+	Ident(String),
+	Int(u64),
+	Float(f64),
+	Str(String),
+	Bytes(Vec<u8>),
+	Op(&'static str),
+	/// A parenthesized, comma-separated list.
+	Parens(Vec<Vec<Token>>),
+	/// A bracketed, comma-separated list.
+	Brackets(Vec<Vec<Token>>),
+}
+
+#[derive(Debug)]
+pub struct Statement {
+	pub tokens: Vec<Token>,
+	pub block: Option<Vec<Statement>>,
+	pub doc: Option<String>,
+	pub pos: usize,
+}
+
+const OPS: [&str; 9] = ["->", "@", ":", "=", ".", "$", "-", "*", "+"];
+
+/// A token before parens and brackets are nested.
+#[derive(Debug)]
+enum Raw {
+	Token(TokenKind),
+	Open(u8),
+	Close(u8),
+	Comma,
+	Semicolon,
+	Comment(String),
+}
+
+#[derive(Debug)]
+struct Lexeme {
+	raw: Raw,
+	pos: usize,
+	line: usize,
+	/// Whether only whitespace precedes it on its line.
+	starts_line: bool,
+}
+
+pub fn statements(source: &str) -> Result<Vec<Statement>, Error> {
+	let lexemes = lex(source)?;
+	let mut cursor = Cursor { lexemes, next: 0 };
+
+	let statements = cursor.statements(false)?;
+	if let Some(lexeme) = cursor.peek() {
+		return Err(Error::new(lexeme.pos, "unexpected '}'"));
+	}
+
+	Ok(statements)
+}
+
+fn lex(source: &str) -> Result<Vec<Lexeme>, Error> {
+	let bytes = source.as_bytes();
+	let mut lexemes = vec![];
+	let mut i = 0;
+	let mut line = 0;
+	let mut starts_line = true;
+
+	while i < bytes.len() {
+		let c = bytes[i];
+		let pos = i;
+
+		if c == b'\n' {
+			line += 1;
+			starts_line = true;
+			i += 1;
+			continue;
+		}
+		if c.is_ascii_whitespace() {
+			i += 1;
+			continue;
+		}
+
+		let raw = match c {
+			b'#' => {
+				let end = source[i..].find('\n').map_or(bytes.len(), |end| i + end);
+				let text = &source[i + 1..end];
+				i = end;
+				Raw::Comment(text.strip_prefix(' ').unwrap_or(text).to_string())
+			}
+			b'(' | b'[' | b'{' => {
+				i += 1;
+				Raw::Open(c)
+			}
+			b')' | b']' | b'}' => {
+				i += 1;
+				Raw::Close(c)
+			}
+			b',' => {
+				i += 1;
+				Raw::Comma
+			}
+			b';' => {
+				i += 1;
+				Raw::Semicolon
+			}
+			b'"' => {
+				let (text, end) = string(source, i)?;
+				i = end;
+				Raw::Token(TokenKind::Str(text))
+			}
+			b'0'..=b'9' => {
+				let (kind, end) = number(source, i)?;
+				i = end;
+				Raw::Token(kind)
+			}
+			c if c == b'_' || c.is_ascii_alphabetic() => {
+				let end = bytes[i..]
+					.iter()
+					.position(|c| !(*c == b'_' || c.is_ascii_alphanumeric()))
+					.map_or(bytes.len(), |end| i + end);
+				let ident = source[i..end].to_string();
+				i = end;
+				Raw::Token(TokenKind::Ident(ident))
+			}
+			_ => match OPS.iter().find(|op| source[i..].starts_with(**op)) {
+				Some(op) => {
+					i += op.len();
+					Raw::Token(TokenKind::Op(op))
+				}
+				None => {
+					let c = source[i..].chars().next().unwrap_or_default();
+					return Err(Error::new(pos, format!("unexpected character '{c}'")));
+				}
+			},
+		};
+
+		lexemes.push(Lexeme {
+			raw,
+			pos,
+			line,
+			starts_line,
+		});
+		starts_line = false;
+	}
+
+	Ok(lexemes)
+}
+
+/// Reads the string literal starting at `start`, returning it and the offset just past it.
+fn string(source: &str, start: usize) -> Result<(String, usize), Error> {
+	let mut text = String::new();
+	let mut chars = source[start + 1..].char_indices();
+
+	while let Some((offset, c)) = chars.next() {
+		match c {
+			'"' => return Ok((text, start + 1 + offset + 1)),
+			'\n' => break,
+			'\\' => {
+				let Some((_, escaped)) = chars.next() else { break };
+				match escaped {
+					'n' => text.push('\n'),
+					't' => text.push('\t'),
+					'r' => text.push('\r'),
+					'a' => text.push('\x07'),
+					'b' => text.push('\x08'),
+					'f' => text.push('\x0c'),
+					'v' => text.push('\x0b'),
+					'x' => {
+						let digits: String = chars.clone().take(2).map(|(_, c)| c).collect();
+						let value = u8::from_str_radix(&digits, 16)
+							.map_err(|_| Error::new(start + 1 + offset, "invalid \\x escape in string"))?;
+						chars.nth(1);
+						text.push(char::from(value));
+					}
+					'0'..='7' => {
+						let mut value = escaped.to_digit(8).unwrap_or_default();
+						for _ in 0..2 {
+							match chars.clone().next().and_then(|(_, c)| c.to_digit(8)) {
+								Some(digit) => {
+									value = value * 8 + digit;
+									chars.next();
+								}
+								None => break,
+							}
+						}
+						text.push(char::from_u32(value).unwrap_or_default());
+					}
+					other => text.push(other),
+				}
+			}
+			c => text.push(c),
+		}
+	}
+
+	Err(Error::new(start, "unterminated string literal"))
+}
+
+/// Reads the number (or `0x"..."` data literal) starting at `start`.
+fn number(source: &str, start: usize) -> Result<(TokenKind, usize), Error> {
+	let bytes = source.as_bytes();
+	let rest = &source[start..];
+
+	if let Some(literal) = rest.strip_prefix("0x\"") {
+		let end = literal
+			.find('"')
+			.ok_or_else(|| Error::new(start, "unterminated data literal"))?;
+		let digits: String = literal[..end].chars().filter(|c| !c.is_whitespace()).collect();
+		if !digits.len().is_multiple_of(2) {
+			return Err(Error::new(start, "data literal has an odd number of hex digits"));
+		}
+
+		let data = (0..digits.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+			.collect::<Result<Vec<u8>, _>>()
+			.map_err(|_| Error::new(start, "invalid hex digit in data literal"))?;
+		return Ok((TokenKind::Bytes(data), start + 3 + end + 1));
+	}
+
+	let word_end = |from: usize| {
+		bytes[from..]
+			.iter()
+			.position(|c| !(c.is_ascii_alphanumeric() || *c == b'_'))
+			.map_or(bytes.len(), |end| from + end)
+	};
+
+	if rest.starts_with("0x") || rest.starts_with("0X") {
+		let end = word_end(start + 2);
+		let value = u64::from_str_radix(&source[start + 2..end], 16)
+			.map_err(|_| Error::new(start, "invalid hex literal"))?;
+		return Ok((TokenKind::Int(value), end));
+	}
+
+	let mut end = start;
+	while end < bytes.len() && bytes[end].is_ascii_digit() {
+		end += 1;
+	}
+
+	let mut is_float = false;
+	if end + 1 < bytes.len() && bytes[end] == b'.' && bytes[end + 1].is_ascii_digit() {
+		is_float = true;
+		end += 1;
+		while end < bytes.len() && bytes[end].is_ascii_digit() {
+			end += 1;
+		}
+	}
+	if end < bytes.len() && (bytes[end] == b'e' || bytes[end] == b'E') {
+		let mut exponent = end + 1;
+		if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+			exponent += 1;
+		}
+		if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+			is_float = true;
+			end = exponent;
+			while end < bytes.len() && bytes[end].is_ascii_digit() {
+				end += 1;
+			}
+		}
+	}
+
+	let text = &source[start..end];
+	let kind = if is_float {
+		TokenKind::Float(
+			text.parse()
+				.map_err(|_| Error::new(start, "invalid float literal"))?,
+		)
+	} else if text.len() > 1 && text.starts_with('0') {
+		TokenKind::Int(
+			u64::from_str_radix(&text[1..], 8).map_err(|_| Error::new(start, "invalid octal literal"))?,
+		)
+	} else {
+		TokenKind::Int(
+			text.parse()
+				.map_err(|_| Error::new(start, "integer literal is too large"))?,
+		)
+	};
+
+	Ok((kind, end))
+}
+
+struct Cursor {
+	lexemes: Vec<Lexeme>,
+	next: usize,
+}
+
+impl Cursor {
+	fn peek(&self) -> Option<&Lexeme> {
+		self.lexemes.get(self.next)
+	}
+
+	fn skip_comments(&mut self) {
+		while let Some(Lexeme {
+			raw: Raw::Comment(_), ..
+		}) = self.peek()
+		{
+			self.next += 1;
+		}
+	}
+
+	/// Statements up to the end of input, or up to and including the `}` closing a block.
+	fn statements(&mut self, in_block: bool) -> Result<Vec<Statement>, Error> {
+		let mut statements = vec![];
+
+		loop {
+			self.skip_comments();
+			let Some(lexeme) = self.peek() else {
+				if in_block {
+					let pos = self.lexemes.last().map_or(0, |lexeme| lexeme.pos);
+					return Err(Error::new(pos, "missing '}'"));
+				}
+				return Ok(statements);
+			};
+
+			if matches!(lexeme.raw, Raw::Close(b'}')) {
+				if in_block {
+					self.next += 1;
+				}
+				return Ok(statements);
+			}
+
+			statements.push(self.statement()?);
+		}
+	}
+
+	fn statement(&mut self) -> Result<Statement, Error> {
+		let pos = self.peek().map_or(0, |lexeme| lexeme.pos);
+		let mut tokens = vec![];
+
+		loop {
+			self.skip_comments();
+			let Some(lexeme) = self.lexemes.get(self.next) else {
+				return Err(Error::new(pos, "statement is missing its ';'"));
+			};
+			let line = lexeme.line;
+			self.next += 1;
+
+			match &lexeme.raw {
+				Raw::Semicolon => {
+					return Ok(Statement {
+						tokens,
+						block: None,
+						doc: self.doc_comment(line),
+						pos,
+					});
+				}
+				Raw::Open(b'{') => {
+					let doc = self.doc_comment(line);
+					return Ok(Statement {
+						tokens,
+						block: Some(self.statements(true)?),
+						doc,
+						pos,
+					});
+				}
+				_ => {
+					self.next -= 1;
+					tokens.push(self.token()?);
+				}
+			}
+		}
+	}
+
+	fn token(&mut self) -> Result<Token, Error> {
+		let lexeme = &self.lexemes[self.next];
+		let pos = lexeme.pos;
+		self.next += 1;
+
+		let kind = match &lexeme.raw {
+			Raw::Token(kind) => kind.clone(),
+			Raw::Open(b'(') => TokenKind::Parens(self.list(b')')?),
+			Raw::Open(b'[') => TokenKind::Brackets(self.list(b']')?),
+			Raw::Close(c) => return Err(Error::new(pos, format!("unexpected '{}'", char::from(*c)))),
+			Raw::Comma => return Err(Error::new(pos, "unexpected ','")),
+			_ => return Err(Error::new(pos, "unexpected token")),
+		};
+
+		Ok(Token { kind, pos })
+	}
+
+	/// The comma-separated items up to the closing `close`.
+	fn list(&mut self, close: u8) -> Result<Vec<Vec<Token>>, Error> {
+		let mut items = vec![];
+		let mut item = vec![];
+
+		loop {
+			self.skip_comments();
+			let Some(lexeme) = self.peek() else {
+				let pos = self.lexemes.last().map_or(0, |lexeme| lexeme.pos);
+				return Err(Error::new(pos, format!("missing '{}'", char::from(close))));
+			};
+
+			match lexeme.raw {
+				Raw::Close(c) if c == close => {
+					self.next += 1;
+					if !item.is_empty() {
+						items.push(item);
+					}
+					return Ok(items);
+				}
+				Raw::Comma => {
+					self.next += 1;
+					items.push(std::mem::take(&mut item));
+				}
+				_ => item.push(self.token()?),
+			}
+		}
+	}
+
+	/// The comment lines right after a `;` or `{` on `line`: starting on that line or the
+	/// next, and running until the first line that isn't a comment.
+	fn doc_comment(&mut self, line: usize) -> Option<String> {
+		let mut doc = String::new();
+		let mut expected = line;
+
+		while let Some(Lexeme {
+			raw: Raw::Comment(text),
+			line: comment_line,
+			starts_line,
+			..
+		}) = self.peek()
+		{
+			let trailing = doc.is_empty() && *comment_line == line;
+			let next_line = *comment_line == expected + 1 && *starts_line;
+			if !(trailing || next_line) {
+				break;
+			}
+
+			doc.push_str(text);
+			doc.push('\n');
+			expected = *comment_line;
+			self.next += 1;
+		}
+
+		(!doc.is_empty()).then_some(doc)
+	}
+}
@@ -0,0 +1,132 @@
+//! MD5, which capnp uses to derive the IDs of nested declarations from their parent's.
+
+const SHIFTS: [u32; 64] = [
+	7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+	9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15, 21, 6, 10, 15, 21, 6,
+	10, 15, 21, 6, 10, 15, 21,
+];
+
+fn constants() -> [u32; 64] {
+	let mut k = [0; 64];
+	for (i, k) in k.iter_mut().enumerate() {
+		*k = ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32;
+	}
+	k
+}
+
+pub fn digest(data: &[u8]) -> [u8; 16] {
+	let k = constants();
+	let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+	let mut message = data.to_vec();
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0);
+	}
+	message.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_le_bytes());
+
+	for chunk in message.chunks(64) {
+		let mut m = [0u32; 16];
+		for (i, word) in m.iter_mut().enumerate() {
+			*word = u32::from_le_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+		}
+
+		let [mut a, mut b, mut c, mut d] = state;
+		for i in 0..64 {
+			let (f, g) = match i / 16 {
+				0 => ((b & c) | (!b & d), i),
+				1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+				2 => (b ^ c ^ d, (3 * i + 5) % 16),
+				_ => (c ^ (b | !d), (7 * i) % 16),
+			};
+
+			let rotated = a
+				.wrapping_add(f)
+				.wrapping_add(k[i])
+				.wrapping_add(m[g])
+				.rotate_left(SHIFTS[i]);
+			a = d;
+			d = c;
+			c = b;
+			b = b.wrapping_add(rotated);
+		}
+
+		state[0] = state[0].wrapping_add(a);
+		state[1] = state[1].wrapping_add(b);
+		state[2] = state[2].wrapping_add(c);
+		state[3] = state[3].wrapping_add(d);
+	}
+
+	let mut out = [0; 16];
+	for (i, word) in state.iter().enumerate() {
+		out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+	}
+	out
+}
+
+/// The first eight bytes of the digest, read big-endian, with the high bit set the way every
+/// capnp ID has it.
+fn id(bytes: &[u8]) -> u64 {
+	let digest = digest(bytes);
+	let mut id = 0u64;
+	for byte in &digest[..8] {
+		id = (id << 8) | u64::from(*byte);
+	}
+	id | (1 << 63)
+}
+
+/// The ID of a declaration named `name` nested in `parent`.
+pub fn child_id(parent: u64, name: &str) -> u64 {
+	let mut bytes = parent.to_le_bytes().to_vec();
+	bytes.extend_from_slice(name.as_bytes());
+	id(&bytes)
+}
+
+/// The ID of the `index`th group or named union declared in `parent`.
+pub fn group_id(parent: u64, index: u16) -> u64 {
+	let mut bytes = parent.to_le_bytes().to_vec();
+	bytes.extend_from_slice(&index.to_le_bytes());
+	id(&bytes)
+}
+
+/// The ID of the struct a method declares inline for its params or results.
+pub fn method_params_id(parent: u64, ordinal: u16, is_results: bool) -> u64 {
+	let mut bytes = parent.to_le_bytes().to_vec();
+	bytes.extend_from_slice(&ordinal.to_le_bytes());
+	bytes.push(u8::from(is_results));
+	id(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(digest: [u8; 16]) -> String {
+		digest.iter().map(|byte| format!("{byte:02x}")).collect()
+	}
+
+	#[test]
+	fn digest_matches_rfc_1321() {
+		assert_eq!(hex(digest(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+		assert_eq!(hex(digest(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+		assert_eq!(hex(digest(b"message digest")), "f96b697d7cb7938d525a2f31aaf161d0");
+		// longer than one 64-byte block once padded
+		assert_eq!(
+			hex(digest(
+				b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+			)),
+			"57edf4a22be3c955ac49da2e2107b67a"
+		);
+	}
+
+	#[test]
+	fn ids_match_the_capnp_tool() {
+		// c++.capnp's `namespace` annotation
+		assert_eq!(child_id(0xbdf87d7bb8304e81, "namespace"), 0xb9c6f99ebf805f2c);
+		// groups of schema.capnp's `Node`, by their place in its field list
+		let node = 0xe682ab4cf923a417;
+		assert_eq!(group_id(node, 7), 0x9ea0b19b37fb4435);
+		assert_eq!(group_id(node, 8), 0xb54ab3364333f598);
+		assert_eq!(group_id(node, 11), 0xec1619d4400a0290);
+	}
+}
@@ -0,0 +1,343 @@
+//! A compiler front end for `.capnp` source, producing the same `CodeGeneratorRequest` the
+//! capnp tool hands its plugins, so schemas can be read without it installed.
+
+mod emit;
+mod layout;
+mod lexer;
+mod md5;
+mod parser;
+mod translate;
+mod value;
+
+use anyhow::{anyhow, Result};
+use capnp::message;
+use capnp::serialize::{self, OwnedSegments};
+use parser::{Application, Decl, DeclKind, Expr, ExprKind, ParamList, ParsedFile};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use translate::Compiler;
+
+/// Files the capnp tool installs alongside itself, for when no import path has them.
+const EMBEDDED: [(&str, &str); 2] = [
+	("capnp/c++.capnp", include_str!("c++.capnp")),
+	("capnp/stream.capnp", include_str!("stream.capnp")),
+];
+
+/// Where `-> stream` finds `StreamResult`, imported implicitly like the capnp tool does.
+const STREAM_IMPORT: &str = "/capnp/stream.capnp";
+
+/// Searched for imports starting with `/`, like the capnp tool does by default.
+const STANDARD_IMPORT_PATHS: [&str; 2] = ["/usr/local/include", "/usr/include"];
+
+/// A syntax error, at a byte offset into the source.
+#[derive(Debug)]
+pub struct Error {
+	pub pos: usize,
+	pub message: String,
+}
+
+impl Error {
+	pub fn new(pos: usize, message: impl Into<String>) -> Self {
+		Error {
+			pos,
+			message: message.into(),
+		}
+	}
+}
+
+pub struct SourceFile {
+	/// As the capnp tool would name it: relative to the directory or import path it was found in.
+	pub name: String,
+	/// Where relative imports are resolved; `None` for embedded files.
+	dir: Option<PathBuf>,
+	text: String,
+	pub parsed: ParsedFile,
+	/// Whether the file was asked for, rather than only imported.
+	pub requested: bool,
+	/// Each import as written, and the index of the file it loaded.
+	pub imports: Vec<(String, usize)>,
+	/// The contents of each `embed` as written.
+	pub embeds: Vec<(String, Vec<u8>)>,
+}
+
+impl SourceFile {
+	/// `name:line:column` for a byte offset into the file.
+	pub fn location(&self, pos: usize) -> String {
+		location(&self.name, &self.text, pos)
+	}
+}
+
+/// Where imports are looked for, mirroring the capnp tool's flags of the same names. Relative
+/// paths are taken from the directory being compiled.
+pub struct Options {
+	pub import_paths: Vec<PathBuf>,
+	pub standard_import: bool,
+	/// Stripped from the start of requested files' names.
+	pub src_prefixes: Vec<PathBuf>,
+}
+
+/// Compiles `files`, relative to `dir`, into the request the capnp tool would have written.
+pub fn compile(dir: &Path, files: &[PathBuf], options: &Options) -> Result<message::Reader<OwnedSegments>> {
+	let mut import_paths: Vec<_> = options.import_paths.iter().map(|path| dir.join(path)).collect();
+	if options.standard_import {
+		import_paths.extend(STANDARD_IMPORT_PATHS.iter().map(PathBuf::from));
+	}
+
+	let mut loader = Loader {
+		files: vec![],
+		loaded: HashMap::new(),
+		import_paths,
+		embedded: options.standard_import,
+	};
+	for file in files {
+		let index = loader.load(&requested_name(file, &options.src_prefixes), Some(dir.join(file)))?;
+		loader.files[index].requested = true;
+	}
+
+	let compiler = Compiler::new(&loader.files)?;
+	let request = compiler.request()?;
+
+	// read back as a plain message, the same as one piped in from the capnp tool
+	let mut bytes = vec![];
+	serialize::write_message(&mut bytes, &request)?;
+	Ok(serialize::read_message(
+		&mut bytes.as_slice(),
+		message::ReaderOptions::new(),
+	)?)
+}
+
+struct Loader {
+	files: Vec<SourceFile>,
+	/// Files already loaded, by canonical path or embedded name.
+	loaded: HashMap<PathBuf, usize>,
+	/// Searched in order for imports starting with `/`.
+	import_paths: Vec<PathBuf>,
+	/// Whether the embedded copies of the standard files can be imported.
+	embedded: bool,
+}
+
+impl Loader {
+	/// Loads a file and everything it imports, returning its index. `path` is `None` for an
+	/// embedded file.
+	fn load(&mut self, name: &str, path: Option<PathBuf>) -> Result<usize> {
+		let (key, text) = match &path {
+			Some(path) => {
+				let text = fs::read_to_string(path).map_err(|err| anyhow!("{}: {err}", path.display()))?;
+				(fs::canonicalize(path)?, text)
+			}
+			None => {
+				let Some((_, text)) = EMBEDDED.iter().find(|(embedded, _)| *embedded == name) else {
+					return Err(anyhow!(
+						"can't import \"/{name}\": there's no built-in copy of it"
+					));
+				};
+				(Path::new("<embedded>").join(name), text.to_string())
+			}
+		};
+		if let Some(index) = self.loaded.get(&key) {
+			return Ok(*index);
+		}
+
+		let parsed = lexer::statements(&text)
+			.and_then(parser::file)
+			.map_err(|err| anyhow!("{}: {}", location(name, &text, err.pos), err.message))?;
+
+		let index = self.files.len();
+		self.loaded.insert(key, index);
+		self.files.push(SourceFile {
+			name: name.to_string(),
+			dir: path
+				.as_ref()
+				.and_then(|path| path.parent())
+				.map(Path::to_path_buf),
+			text,
+			parsed,
+			requested: false,
+			imports: vec![],
+			embeds: vec![],
+		});
+
+		let mut found = Found::default();
+		found.file(&self.files[index].parsed);
+
+		for (path, pos) in found.imports {
+			if self.files[index].imports.iter().any(|(name, _)| *name == path) {
+				continue;
+			}
+			let (name, resolved) = self.resolve(index, &path, pos)?;
+			let imported = self.load(&name, resolved)?;
+			self.files[index].imports.push((path, imported));
+		}
+
+		for (path, pos) in found.embeds {
+			let (_, resolved) = self.resolve(index, &path, pos)?;
+			let file = &self.files[index];
+			let Some(resolved) = resolved else {
+				return Err(anyhow!("{}: can't embed \"{path}\"", file.location(pos)));
+			};
+			let data = fs::read(&resolved).map_err(|err| anyhow!("{}: {err}", file.location(pos)))?;
+			self.files[index].embeds.push((path, data));
+		}
+
+		Ok(index)
+	}
+
+	/// Finds the file `path` refers to from the file at `from`: its name, and where it is on
+	/// disk, or `None` when it is embedded.
+	fn resolve(&self, from: usize, path: &str, pos: usize) -> Result<(String, Option<PathBuf>)> {
+		let file = &self.files[from];
+
+		if let Some(absolute) = path.strip_prefix('/') {
+			let name = normalize(Path::new(absolute));
+			for dir in &self.import_paths {
+				let candidate = dir.join(&name);
+				if candidate.is_file() {
+					return Ok((name, Some(candidate)));
+				}
+			}
+			if self.embedded && EMBEDDED.iter().any(|(embedded, _)| *embedded == name) {
+				return Ok((name, None));
+			}
+		} else {
+			let name = match file.name.rfind('/') {
+				Some(slash) => normalize(&Path::new(&file.name[..slash]).join(path)),
+				None => normalize(Path::new(path)),
+			};
+			match &file.dir {
+				Some(dir) => {
+					let candidate = dir.join(path);
+					if candidate.is_file() {
+						return Ok((name, Some(candidate)));
+					}
+				}
+				None if EMBEDDED.iter().any(|(embedded, _)| *embedded == name) => return Ok((name, None)),
+				None => {}
+			}
+		}
+
+		Err(anyhow!("{}: can't find \"{path}\"", file.location(pos)))
+	}
+}
+
+/// A requested file's name, without the first of `src_prefixes` it starts with.
+fn requested_name(file: &Path, src_prefixes: &[PathBuf]) -> String {
+	let name = normalize(file);
+
+	for prefix in src_prefixes {
+		let prefix = normalize(prefix);
+		if let Some(rest) = name.strip_prefix(&prefix).and_then(|rest| rest.strip_prefix('/')) {
+			return rest.to_string();
+		}
+	}
+
+	name
+}
+
+/// Drops `.` components and folds `..` into the component before it.
+fn normalize(path: &Path) -> String {
+	let mut parts: Vec<String> = vec![];
+
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir if parts.last().is_some_and(|last| last != "..") => {
+				parts.pop();
+			}
+			Component::RootDir => parts.push(String::new()),
+			other => parts.push(other.as_os_str().to_string_lossy().into_owned()),
+		}
+	}
+
+	parts.join("/")
+}
+
+fn location(name: &str, text: &str, pos: usize) -> String {
+	let before = &text[..pos.min(text.len())];
+	let line = before.matches('\n').count() + 1;
+	let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+
+	format!("{name}:{line}:{column}")
+}
+
+/// The `import` and `embed` expressions in a file, with their positions.
+#[derive(Default)]
+struct Found {
+	imports: Vec<(String, usize)>,
+	embeds: Vec<(String, usize)>,
+}
+
+impl Found {
+	fn file(&mut self, parsed: &ParsedFile) {
+		self.annotations(&parsed.annotations);
+		for decl in &parsed.decls {
+			self.decl(decl);
+		}
+	}
+
+	fn decl(&mut self, decl: &Decl) {
+		self.annotations(&decl.annotations);
+
+		match &decl.kind {
+			DeclKind::Using(target) => self.expr(target),
+			DeclKind::Const { type_, value } => {
+				self.expr(type_);
+				self.expr(value);
+			}
+			DeclKind::Interface { extends, .. } => extends.iter().for_each(|expr| self.expr(expr)),
+			DeclKind::Method { params, results, .. } => {
+				for list in [params, results] {
+					match list {
+						ParamList::Type(type_) => self.expr(type_),
+						ParamList::Stream(pos) => self.imports.push((STREAM_IMPORT.to_string(), *pos)),
+						ParamList::Fields(params, _) => {
+							for param in params {
+								self.expr(&param.type_);
+								if let Some(default) = &param.default {
+									self.expr(default);
+								}
+								self.annotations(&param.annotations);
+							}
+						}
+					}
+				}
+			}
+			DeclKind::Field { type_, default } => {
+				self.expr(type_);
+				if let Some(default) = default {
+					self.expr(default);
+				}
+			}
+			DeclKind::Annotation { type_, .. } => self.expr(type_),
+			_ => {}
+		}
+
+		for nested in &decl.nested {
+			self.decl(nested);
+		}
+	}
+
+	fn annotations(&mut self, annotations: &[Application]) {
+		for annotation in annotations {
+			self.expr(&annotation.name);
+			if let Some(value) = &annotation.value {
+				self.expr(value);
+			}
+		}
+	}
+
+	fn expr(&mut self, expr: &Expr) {
+		match &expr.kind {
+			ExprKind::Import(path) => self.imports.push((path.clone(), expr.pos)),
+			ExprKind::Embed(path) => self.embeds.push((path.clone(), expr.pos)),
+			ExprKind::Member(parent, _) => self.expr(parent),
+			ExprKind::Apply(target, params) => {
+				self.expr(target);
+				params.iter().for_each(|param| self.expr(&param.value));
+			}
+			ExprKind::List(items) => items.iter().for_each(|item| self.expr(item)),
+			ExprKind::Tuple(params) => params.iter().for_each(|param| self.expr(&param.value)),
+			_ => {}
+		}
+	}
+}
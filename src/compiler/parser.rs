@@ -0,0 +1,699 @@
+//! Turns statements into declarations.
+
+use super::lexer::{Statement, Token, TokenKind};
+use super::Error;
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+	pub kind: ExprKind,
+	pub pos: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+	Int(u64),
+	NegativeInt(u64),
+	Float(f64),
+	Str(String),
+	Bytes(Vec<u8>),
+	/// A name looked up through the enclosing scopes.
+	Name(String),
+	/// `.Name`, looked up from the top of the file.
+	Absolute(String),
+	Import(String),
+	Embed(String),
+	Member(Box<Expr>, String),
+	/// Generic arguments, e.g. `List(Text)`.
+	Apply(Box<Expr>, Vec<Param>),
+	List(Vec<Expr>),
+	/// A struct value, e.g. `(name = "x", value = 1)`.
+	Tuple(Vec<Param>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+	pub name: Option<String>,
+	pub value: Expr,
+}
+
+/// An annotation applied with `$name(value)`.
+#[derive(Debug)]
+pub struct Application {
+	pub name: Expr,
+	/// `None` for `$name` without a value.
+	pub value: Option<Expr>,
+	pub pos: usize,
+}
+
+#[derive(Debug)]
+pub struct Decl {
+	/// Empty for an unnamed union.
+	pub name: String,
+	pub pos: usize,
+	/// The `@N` after the name: an ordinal for members, an ID for everything else.
+	pub number: Option<(u64, usize)>,
+	pub doc: Option<String>,
+	pub annotations: Vec<Application>,
+	pub kind: DeclKind,
+	pub nested: Vec<Decl>,
+}
+
+#[derive(Debug)]
+pub enum DeclKind {
+	Using(Expr),
+	Const {
+		type_: Expr,
+		value: Expr,
+	},
+	Struct {
+		params: Vec<String>,
+	},
+	Enum,
+	Enumerant,
+	Interface {
+		params: Vec<String>,
+		extends: Vec<Expr>,
+	},
+	Method {
+		implicit: Vec<String>,
+		params: ParamList,
+		results: ParamList,
+	},
+	Field {
+		type_: Expr,
+		default: Option<Expr>,
+	},
+	Union,
+	Group,
+	Annotation {
+		targets: Vec<String>,
+		type_: Expr,
+	},
+}
+
+#[derive(Debug)]
+pub enum ParamList {
+	/// An existing struct type, e.g. `foo @0 Request -> Response`.
+	Type(Expr),
+	/// Params declared inline, which get their own struct node.
+	Fields(Vec<MethodParam>, usize),
+	/// `-> stream`, which returns `StreamResult` from `/capnp/stream.capnp`.
+	Stream(usize),
+}
+
+#[derive(Debug)]
+pub struct MethodParam {
+	pub name: String,
+	pub pos: usize,
+	pub type_: Expr,
+	pub default: Option<Expr>,
+	pub annotations: Vec<Application>,
+}
+
+#[derive(Debug)]
+pub struct ParsedFile {
+	pub id: Option<u64>,
+	pub doc: Option<String>,
+	pub annotations: Vec<Application>,
+	pub decls: Vec<Decl>,
+}
+
+/// What kind of body a statement appears in, which decides how it is read.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+	File,
+	Struct,
+	Enum,
+	Interface,
+}
+
+pub fn file(statements: Vec<Statement>) -> Result<ParsedFile, Error> {
+	let mut parsed = ParsedFile {
+		id: None,
+		doc: None,
+		annotations: vec![],
+		decls: vec![],
+	};
+
+	for statement in statements {
+		let mut tokens = Tokens::new(&statement.tokens, statement.pos);
+		match tokens.peek() {
+			Some(TokenKind::Op("@")) => {
+				tokens.next();
+				parsed.id = Some(tokens.int()?);
+				parsed.doc = statement.doc;
+				tokens.end()?;
+			}
+			Some(TokenKind::Op("$")) => {
+				parsed.annotations.extend(tokens.annotations()?);
+				tokens.end()?;
+			}
+			_ => parsed.decls.push(decl(statement, Context::File)?),
+		}
+	}
+
+	Ok(parsed)
+}
+
+fn block(statement: Statement, context: Context) -> Result<Vec<Decl>, Error> {
+	match statement.block {
+		Some(statements) => statements
+			.into_iter()
+			.map(|statement| decl(statement, context))
+			.collect(),
+		None => Err(Error::new(statement.pos, "expected a '{' block")),
+	}
+}
+
+fn no_block(statement: &Statement) -> Result<(), Error> {
+	match statement.block {
+		Some(_) => Err(Error::new(statement.pos, "unexpected '{' block")),
+		None => Ok(()),
+	}
+}
+
+fn decl(statement: Statement, context: Context) -> Result<Decl, Error> {
+	let mut tokens = Tokens::new(&statement.tokens, statement.pos);
+	let pos = statement.pos;
+
+	let keyword = match tokens.peek() {
+		Some(TokenKind::Ident(keyword)) => keyword.clone(),
+		_ => return Err(Error::new(tokens.pos(), "expected a declaration")),
+	};
+
+	// keywords are only reserved at the start of a declaration, so `struct :group` is a group
+	// named "struct"
+	let after_name = match tokens.peek_at(1) {
+		Some(TokenKind::Op("@")) => 3,
+		_ => 1,
+	};
+	let is_member = matches!(
+		(context, tokens.peek_at(after_name)),
+		(Context::Struct, Some(TokenKind::Op(":")))
+			| (
+				Context::Interface,
+				Some(TokenKind::Parens(_) | TokenKind::Brackets(_))
+			)
+	);
+	let keyword = if is_member { String::new() } else { keyword };
+
+	let nested_decls = matches!(context, Context::File | Context::Struct | Context::Interface);
+	let mut decl = Decl {
+		name: String::new(),
+		pos,
+		number: None,
+		doc: statement.doc.clone(),
+		annotations: vec![],
+		kind: DeclKind::Enum,
+		nested: vec![],
+	};
+
+	match keyword.as_str() {
+		"using" if nested_decls => {
+			tokens.next();
+			let name = match (tokens.peek(), tokens.peek_at(1)) {
+				(Some(TokenKind::Ident(name)), Some(TokenKind::Op("="))) => {
+					let name = name.clone();
+					tokens.next();
+					tokens.next();
+					Some(name)
+				}
+				_ => None,
+			};
+			let target = tokens.expr()?;
+			decl.name = match name {
+				Some(name) => name,
+				None => match &target.kind {
+					ExprKind::Member(_, name) | ExprKind::Name(name) | ExprKind::Absolute(name) => {
+						name.clone()
+					}
+					_ => return Err(Error::new(target.pos, "'using' needs a name for this target")),
+				},
+			};
+			decl.kind = DeclKind::Using(target);
+			tokens.end()?;
+			no_block(&statement)?;
+		}
+		"const" if nested_decls => {
+			tokens.next();
+			decl.name = tokens.ident()?;
+			decl.number = tokens.number()?;
+			tokens.op(":")?;
+			let type_ = tokens.expr()?;
+			tokens.op("=")?;
+			let value = tokens.expr()?;
+			decl.annotations = tokens.annotations()?;
+			decl.kind = DeclKind::Const { type_, value };
+			tokens.end()?;
+			no_block(&statement)?;
+		}
+		"annotation" if nested_decls => {
+			tokens.next();
+			decl.name = tokens.ident()?;
+			decl.number = tokens.number()?;
+			let targets = match tokens.next() {
+				Some(Token {
+					kind: TokenKind::Parens(items),
+					pos,
+				}) => items
+					.iter()
+					.map(|item| match item.as_slice() {
+						[Token {
+							kind: TokenKind::Ident(target),
+							..
+						}] => Ok(target.clone()),
+						[Token {
+							kind: TokenKind::Op("*"),
+							..
+						}] => Ok("*".to_string()),
+						_ => Err(Error::new(*pos, "expected an annotation target")),
+					})
+					.collect::<Result<_, _>>()?,
+				_ => return Err(Error::new(tokens.pos(), "expected the annotation's targets")),
+			};
+			tokens.op(":")?;
+			let type_ = tokens.expr()?;
+			decl.annotations = tokens.annotations()?;
+			decl.kind = DeclKind::Annotation { targets, type_ };
+			tokens.end()?;
+			no_block(&statement)?;
+		}
+		"struct" | "interface" if nested_decls => {
+			tokens.next();
+			decl.name = tokens.ident()?;
+			decl.number = tokens.number()?;
+			let params = tokens.params()?;
+
+			let mut extends = vec![];
+			if keyword == "interface" {
+				if let Some(TokenKind::Ident(word)) = tokens.peek() {
+					if word == "extends" {
+						tokens.next();
+						match tokens.next() {
+							Some(Token {
+								kind: TokenKind::Parens(items),
+								..
+							}) => {
+								for item in items {
+									extends.push(Tokens::new(item, pos).only_expr()?);
+								}
+							}
+							_ => return Err(Error::new(tokens.pos(), "expected '(' after 'extends'")),
+						}
+					}
+				}
+			}
+
+			decl.annotations = tokens.annotations()?;
+			tokens.end()?;
+
+			if keyword == "struct" {
+				decl.kind = DeclKind::Struct { params };
+				decl.nested = block(statement, Context::Struct)?;
+			} else {
+				decl.kind = DeclKind::Interface { params, extends };
+				decl.nested = block(statement, Context::Interface)?;
+			}
+		}
+		"enum" if nested_decls => {
+			tokens.next();
+			decl.name = tokens.ident()?;
+			decl.number = tokens.number()?;
+			decl.annotations = tokens.annotations()?;
+			decl.kind = DeclKind::Enum;
+			tokens.end()?;
+			decl.nested = block(statement, Context::Enum)?;
+		}
+		"union" if context == Context::Struct => {
+			tokens.next();
+			decl.number = tokens.number()?;
+			decl.annotations = tokens.annotations()?;
+			decl.kind = DeclKind::Union;
+			tokens.end()?;
+			decl.nested = block(statement, Context::Struct)?;
+		}
+		_ => {
+			decl.name = tokens.ident()?;
+			decl.number = tokens.number()?;
+
+			match context {
+				Context::File => return Err(Error::new(pos, "expected a declaration")),
+				Context::Enum => {
+					decl.annotations = tokens.annotations()?;
+					decl.kind = DeclKind::Enumerant;
+					tokens.end()?;
+					no_block(&statement)?;
+				}
+				Context::Interface => {
+					let implicit = match tokens.peek() {
+						Some(TokenKind::Brackets(_)) => match tokens.next() {
+							Some(Token {
+								kind: TokenKind::Brackets(items),
+								..
+							}) => names(items, pos)?,
+							_ => vec![],
+						},
+						_ => vec![],
+					};
+					let params = tokens.param_list()?;
+					let results = match tokens.peek() {
+						Some(TokenKind::Op("->")) => {
+							tokens.next();
+							match tokens.peek() {
+								Some(TokenKind::Ident(word)) if word == "stream" => {
+									let pos = tokens.pos();
+									tokens.next();
+									ParamList::Stream(pos)
+								}
+								_ => tokens.param_list()?,
+							}
+						}
+						_ => ParamList::Fields(vec![], pos),
+					};
+					decl.annotations = tokens.annotations()?;
+					decl.kind = DeclKind::Method {
+						implicit,
+						params,
+						results,
+					};
+					tokens.end()?;
+					no_block(&statement)?;
+				}
+				Context::Struct => {
+					tokens.op(":")?;
+					let group = match tokens.peek() {
+						Some(TokenKind::Ident(word)) if word == "union" || word == "group" => {
+							Some(word == "union")
+						}
+						_ => None,
+					};
+
+					match group {
+						Some(is_union) => {
+							tokens.next();
+							decl.annotations = tokens.annotations()?;
+							decl.kind = if is_union {
+								DeclKind::Union
+							} else {
+								DeclKind::Group
+							};
+							tokens.end()?;
+							decl.nested = block(statement, Context::Struct)?;
+						}
+						None => {
+							let type_ = tokens.expr()?;
+							let default = match tokens.peek() {
+								Some(TokenKind::Op("=")) => {
+									tokens.next();
+									Some(tokens.expr()?)
+								}
+								_ => None,
+							};
+							decl.annotations = tokens.annotations()?;
+							decl.kind = DeclKind::Field { type_, default };
+							tokens.end()?;
+							no_block(&statement)?;
+						}
+					}
+				}
+			}
+		}
+	}
+
+	Ok(decl)
+}
+
+/// A list of bare names, such as generic parameters.
+fn names(items: &[Vec<Token>], pos: usize) -> Result<Vec<String>, Error> {
+	items
+		.iter()
+		.map(|item| match item.as_slice() {
+			[Token {
+				kind: TokenKind::Ident(name),
+				..
+			}] => Ok(name.clone()),
+			_ => Err(Error::new(
+				item.first().map_or(pos, |token| token.pos),
+				"expected a name",
+			)),
+		})
+		.collect()
+}
+
+struct Tokens<'a> {
+	tokens: &'a [Token],
+	next: usize,
+	/// Where the statement starts, for errors at its end.
+	start: usize,
+}
+
+impl<'a> Tokens<'a> {
+	fn new(tokens: &'a [Token], start: usize) -> Self {
+		Tokens {
+			tokens,
+			next: 0,
+			start,
+		}
+	}
+
+	fn peek(&self) -> Option<&'a TokenKind> {
+		self.peek_at(0)
+	}
+
+	fn peek_at(&self, offset: usize) -> Option<&'a TokenKind> {
+		self.tokens.get(self.next + offset).map(|token| &token.kind)
+	}
+
+	fn next(&mut self) -> Option<&'a Token> {
+		let token = self.tokens.get(self.next);
+		self.next += 1;
+		token
+	}
+
+	fn pos(&self) -> usize {
+		self.tokens
+			.get(self.next)
+			.or(self.tokens.last())
+			.map_or(self.start, |token| token.pos)
+	}
+
+	fn end(&self) -> Result<(), Error> {
+		match self.tokens.get(self.next) {
+			Some(token) => Err(Error::new(token.pos, "unexpected token")),
+			None => Ok(()),
+		}
+	}
+
+	fn ident(&mut self) -> Result<String, Error> {
+		match self.peek() {
+			Some(TokenKind::Ident(name)) => {
+				self.next();
+				Ok(name.clone())
+			}
+			_ => Err(Error::new(self.pos(), "expected a name")),
+		}
+	}
+
+	fn op(&mut self, op: &str) -> Result<(), Error> {
+		match self.peek() {
+			Some(TokenKind::Op(found)) if *found == op => {
+				self.next();
+				Ok(())
+			}
+			_ => Err(Error::new(self.pos(), format!("expected '{op}'"))),
+		}
+	}
+
+	fn int(&mut self) -> Result<u64, Error> {
+		match self.peek() {
+			Some(TokenKind::Int(value)) => {
+				self.next();
+				Ok(*value)
+			}
+			_ => Err(Error::new(self.pos(), "expected an integer")),
+		}
+	}
+
+	/// An optional `@N`.
+	fn number(&mut self) -> Result<Option<(u64, usize)>, Error> {
+		match self.peek() {
+			Some(TokenKind::Op("@")) => {
+				self.next();
+				let pos = self.pos();
+				Ok(Some((self.int()?, pos)))
+			}
+			_ => Ok(None),
+		}
+	}
+
+	/// Optional generic parameters, e.g. `(Key, Value)`.
+	fn params(&mut self) -> Result<Vec<String>, Error> {
+		match self.peek() {
+			Some(TokenKind::Parens(items)) => {
+				let pos = self.pos();
+				self.next();
+				names(items, pos)
+			}
+			_ => Ok(vec![]),
+		}
+	}
+
+	fn annotations(&mut self) -> Result<Vec<Application>, Error> {
+		let mut annotations = vec![];
+
+		while let Some(TokenKind::Op("$")) = self.peek() {
+			let pos = self.pos();
+			self.next();
+
+			let expr = self.expr()?;
+			let (name, value) = match expr.kind {
+				ExprKind::Apply(name, mut params) => {
+					let value = match params.as_slice() {
+						[Param { name: None, .. }] => params.remove(0).value,
+						_ => Expr {
+							kind: ExprKind::Tuple(params),
+							pos: expr.pos,
+						},
+					};
+					(*name, Some(value))
+				}
+				_ => (expr, None),
+			};
+
+			annotations.push(Application { name, value, pos });
+		}
+
+		Ok(annotations)
+	}
+
+	/// Method params or results: an inline list, or the name of a struct.
+	fn param_list(&mut self) -> Result<ParamList, Error> {
+		let pos = self.pos();
+		let Some(TokenKind::Parens(items)) = self.peek() else {
+			return Ok(ParamList::Type(self.expr()?));
+		};
+		self.next();
+
+		let mut params = vec![];
+		for item in items {
+			let mut tokens = Tokens::new(item, pos);
+			let pos = tokens.pos();
+			let name = tokens.ident()?;
+			tokens.op(":")?;
+			let type_ = tokens.expr()?;
+			let default = match tokens.peek() {
+				Some(TokenKind::Op("=")) => {
+					tokens.next();
+					Some(tokens.expr()?)
+				}
+				_ => None,
+			};
+			let annotations = tokens.annotations()?;
+			tokens.end()?;
+
+			params.push(MethodParam {
+				name,
+				pos,
+				type_,
+				default,
+				annotations,
+			});
+		}
+
+		Ok(ParamList::Fields(params, pos))
+	}
+
+	/// An expression that makes up the whole token list.
+	fn only_expr(&mut self) -> Result<Expr, Error> {
+		let expr = self.expr()?;
+		self.end()?;
+		Ok(expr)
+	}
+
+	fn expr(&mut self) -> Result<Expr, Error> {
+		let pos = self.pos();
+		let Some(token) = self.next() else {
+			return Err(Error::new(pos, "expected an expression"));
+		};
+
+		let kind = match &token.kind {
+			TokenKind::Int(value) => ExprKind::Int(*value),
+			TokenKind::Float(value) => ExprKind::Float(*value),
+			TokenKind::Str(text) => ExprKind::Str(text.clone()),
+			TokenKind::Bytes(data) => ExprKind::Bytes(data.clone()),
+			TokenKind::Op("-") => match self.next().map(|token| &token.kind) {
+				Some(TokenKind::Int(value)) => ExprKind::NegativeInt(*value),
+				Some(TokenKind::Float(value)) => ExprKind::Float(-value),
+				Some(TokenKind::Ident(name)) if name == "inf" => ExprKind::Float(f64::NEG_INFINITY),
+				_ => return Err(Error::new(pos, "expected a number after '-'")),
+			},
+			TokenKind::Op(".") => ExprKind::Absolute(self.ident()?),
+			TokenKind::Ident(keyword) if keyword == "import" || keyword == "embed" => {
+				let path = match self.next().map(|token| &token.kind) {
+					Some(TokenKind::Str(path)) => path.clone(),
+					_ => return Err(Error::new(pos, format!("expected a path after '{keyword}'"))),
+				};
+				match keyword.as_str() {
+					"import" => ExprKind::Import(path),
+					_ => ExprKind::Embed(path),
+				}
+			}
+			TokenKind::Ident(name) => ExprKind::Name(name.clone()),
+			TokenKind::Parens(items) => ExprKind::Tuple(self.args(items)?),
+			TokenKind::Brackets(items) => ExprKind::List(
+				items
+					.iter()
+					.map(|item| Tokens::new(item, pos).only_expr())
+					.collect::<Result<_, _>>()?,
+			),
+			_ => return Err(Error::new(pos, "expected an expression")),
+		};
+
+		let mut expr = Expr { kind, pos };
+		loop {
+			match self.peek() {
+				Some(TokenKind::Op(".")) => {
+					self.next();
+					let name = self.ident()?;
+					expr = Expr {
+						kind: ExprKind::Member(Box::new(expr), name),
+						pos,
+					};
+				}
+				Some(TokenKind::Parens(items)) => {
+					self.next();
+					expr = Expr {
+						kind: ExprKind::Apply(Box::new(expr), self.args(items)?),
+						pos,
+					};
+				}
+				_ => return Ok(expr),
+			}
+		}
+	}
+
+	/// The items of a parenthesized list, each either `name = value` or a bare value.
+	fn args(&self, items: &[Vec<Token>]) -> Result<Vec<Param>, Error> {
+		items
+			.iter()
+			.map(|item| {
+				let mut tokens = Tokens::new(item, self.start);
+				match (tokens.peek(), tokens.peek_at(1)) {
+					(Some(TokenKind::Ident(name)), Some(TokenKind::Op("="))) => {
+						let name = name.clone();
+						tokens.next();
+						tokens.next();
+						Ok(Param {
+							name: Some(name),
+							value: tokens.only_expr()?,
+						})
+					}
+					_ => Ok(Param {
+						name: None,
+						value: tokens.only_expr()?,
+					}),
+				}
+			})
+			.collect()
+	}
+}
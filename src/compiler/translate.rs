@@ -0,0 +1,1212 @@
+//! Builds the node graph from parsed files: assigns IDs, resolves names and lays out structs.
+
+use super::layout::{Container, Layout};
+use super::md5::{child_id, group_id, method_params_id};
+use super::parser::{Application, Decl, DeclKind, Expr, ExprKind, MethodParam, ParamList};
+use super::SourceFile;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+/// A type as written in a schema, with names resolved to node IDs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeRef {
+	Void,
+	Bool,
+	Int8,
+	Int16,
+	Int32,
+	Int64,
+	Uint8,
+	Uint16,
+	Uint32,
+	Uint64,
+	Float32,
+	Float64,
+	Text,
+	Data,
+	List(Box<TypeRef>),
+	Enum(u64, Brand),
+	Struct(u64, Brand),
+	Interface(u64, Brand),
+	AnyPointer,
+	AnyStruct,
+	AnyList,
+	Capability,
+	Param { scope_id: u64, index: u16 },
+	ImplicitParam(u16),
+}
+
+/// Generic scopes and what their parameters are bound to, innermost first. `None` means the
+/// scope's own parameters are passed through.
+pub type Brand = Vec<(u64, Option<Vec<TypeRef>>)>;
+
+/// How a field of this type is stored.
+pub enum Size {
+	Void,
+	/// log2 of the size in bits.
+	Data(u32),
+	Pointer,
+}
+
+impl TypeRef {
+	/// This type with the generic parameters `brand` binds replaced by what they are bound to.
+	pub fn substitute(&self, brand: &Brand) -> TypeRef {
+		let substitute_brand = |inner: &Brand| -> Brand {
+			inner
+				.iter()
+				.map(|(scope, bindings)| {
+					let bindings = match bindings {
+						Some(bindings) => {
+							Some(bindings.iter().map(|type_| type_.substitute(brand)).collect())
+						}
+						// passed through, so bound to whatever the outer brand binds it to
+						None => brand
+							.iter()
+							.find(|(outer, _)| outer == scope)
+							.and_then(|(_, bindings)| bindings.clone()),
+					};
+					(*scope, bindings)
+				})
+				.collect()
+		};
+
+		match self {
+			TypeRef::Param { scope_id, index } => match brand.iter().find(|(scope, _)| scope == scope_id) {
+				Some((_, Some(bindings))) => bindings
+					.get(*index as usize)
+					.cloned()
+					.unwrap_or(TypeRef::AnyPointer),
+				_ => self.clone(),
+			},
+			TypeRef::List(element) => TypeRef::List(Box::new(element.substitute(brand))),
+			TypeRef::Enum(id, inner) => TypeRef::Enum(*id, substitute_brand(inner)),
+			TypeRef::Struct(id, inner) => TypeRef::Struct(*id, substitute_brand(inner)),
+			TypeRef::Interface(id, inner) => TypeRef::Interface(*id, substitute_brand(inner)),
+			_ => self.clone(),
+		}
+	}
+
+	pub fn size(&self) -> Size {
+		match self {
+			TypeRef::Void => Size::Void,
+			TypeRef::Bool => Size::Data(0),
+			TypeRef::Int8 | TypeRef::Uint8 => Size::Data(3),
+			TypeRef::Int16 | TypeRef::Uint16 | TypeRef::Enum(..) => Size::Data(4),
+			TypeRef::Int32 | TypeRef::Uint32 | TypeRef::Float32 => Size::Data(5),
+			TypeRef::Int64 | TypeRef::Uint64 | TypeRef::Float64 => Size::Data(6),
+			_ => Size::Pointer,
+		}
+	}
+}
+
+/// As a schema writes it, but with nodes named by ID, since a type doesn't know their names.
+impl Display for TypeRef {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TypeRef::List(element) => write!(f, "List({element})"),
+			TypeRef::Enum(id, brand) | TypeRef::Struct(id, brand) | TypeRef::Interface(id, brand) => {
+				write!(f, "@{id:#018x}")?;
+				if let Some((_, Some(bindings))) = brand.iter().find(|(scope, _)| scope == id) {
+					let bindings: Vec<String> = bindings.iter().map(ToString::to_string).collect();
+					write!(f, "({})", bindings.join(", "))?;
+				}
+				Ok(())
+			}
+			TypeRef::Param { scope_id, index } => write!(f, "parameter {index} of @{scope_id:#018x}"),
+			TypeRef::ImplicitParam(index) => write!(f, "implicit parameter {index}"),
+			// the rest are spelled as in a schema, apart from the unsigned integers
+			TypeRef::Uint8 => write!(f, "UInt8"),
+			TypeRef::Uint16 => write!(f, "UInt16"),
+			TypeRef::Uint32 => write!(f, "UInt32"),
+			TypeRef::Uint64 => write!(f, "UInt64"),
+			_ => write!(f, "{self:?}"),
+		}
+	}
+}
+
+/// Where names in an expression are looked up.
+#[derive(Clone, Copy, Default)]
+pub struct Lookup<'a> {
+	/// The innermost node in scope; its parents are searched after it.
+	pub node: usize,
+	/// Implicit parameters of the method whose params are being compiled.
+	pub implicit: &'a [String],
+	/// The params struct those parameters belong to.
+	pub params_struct: Option<u64>,
+}
+
+impl Lookup<'_> {
+	pub fn new(node: usize) -> Self {
+		Lookup {
+			node,
+			..Lookup::default()
+		}
+	}
+}
+
+/// What a name resolves to.
+enum Entity {
+	/// A declaration, with the generic arguments given along the way.
+	Node(usize, Vec<(usize, Vec<TypeRef>)>),
+	Type(TypeRef),
+	/// `List`, still waiting for its element type.
+	List,
+}
+
+enum Member<'a> {
+	Node(usize),
+	Alias(&'a Expr),
+}
+
+#[derive(Default)]
+pub struct StructDef<'a> {
+	pub is_group: bool,
+	pub data_words: u16,
+	pub pointers: u16,
+	pub discriminant_count: u16,
+	pub discriminant_offset: u32,
+	/// In the order capnp lists them, which is by ordinal with groups where they are first used.
+	pub fields: Vec<FieldDef<'a>>,
+	/// Where default values and annotations are resolved.
+	pub lookup: Lookup<'a>,
+}
+
+pub struct FieldDef<'a> {
+	pub name: &'a str,
+	pub code_order: u16,
+	pub discriminant: Option<u16>,
+	pub ordinal: Option<u16>,
+	pub annotations: &'a [Application],
+	pub doc: Option<&'a str>,
+	pub kind: FieldKind<'a>,
+}
+
+pub enum FieldKind<'a> {
+	Slot {
+		type_: TypeRef,
+		offset: u32,
+		default: Option<&'a Expr>,
+	},
+	Group(u64),
+}
+
+pub enum Params<'a> {
+	/// An existing struct, named in place of a param list.
+	Type(&'a Expr),
+	/// The struct generated for a param list declared inline.
+	Struct(u64),
+	/// `StreamResult`, for `-> stream`.
+	Stream(usize),
+}
+
+pub struct MethodDef<'a> {
+	pub decl: &'a Decl,
+	pub code_order: u16,
+	pub implicit: &'a [String],
+	pub params: Params<'a>,
+	pub results: Params<'a>,
+}
+
+pub enum Kind<'a> {
+	File,
+	Struct(StructDef<'a>),
+	/// Enumerants by ordinal, with their code order.
+	Enum(Vec<(u16, &'a Decl)>),
+	Interface {
+		extends: &'a [Expr],
+		methods: Vec<MethodDef<'a>>,
+	},
+	Const {
+		type_: &'a Expr,
+		value: &'a Expr,
+	},
+	Annotation {
+		targets: &'a [String],
+		type_: &'a Expr,
+	},
+}
+
+pub struct Node<'a> {
+	pub id: u64,
+	pub display_name: String,
+	pub prefix_len: usize,
+	pub scope_id: u64,
+	/// The enclosing node names are looked up in next; `None` for files.
+	pub parent: Option<usize>,
+	pub file: usize,
+	pub params: &'a [String],
+	pub is_generic: bool,
+	/// Declarations nested in this one, in code order.
+	pub nested: Vec<usize>,
+	members: HashMap<&'a str, Member<'a>>,
+	pub doc: Option<&'a str>,
+	pub annotations: &'a [Application],
+	pub kind: Kind<'a>,
+	decl: Option<&'a Decl>,
+}
+
+pub struct Compiler<'a> {
+	pub files: &'a [SourceFile],
+	pub nodes: Vec<Node<'a>>,
+	by_id: HashMap<u64, usize>,
+	/// The node of each file, by file index.
+	pub file_nodes: Vec<usize>,
+}
+
+impl<'a> Compiler<'a> {
+	pub fn new(files: &'a [SourceFile]) -> Result<Self> {
+		let mut compiler = Compiler {
+			files,
+			nodes: vec![],
+			by_id: HashMap::new(),
+			file_nodes: vec![],
+		};
+
+		for file in 0..files.len() {
+			compiler.declare_file(file)?;
+		}
+
+		// groups and param structs are added as they are found, and need no translating
+		for index in 0..compiler.nodes.len() {
+			match compiler.nodes[index].decl.map(|decl| &decl.kind) {
+				Some(DeclKind::Struct { .. }) => compiler.translate_struct(index)?,
+				Some(DeclKind::Interface { .. }) => compiler.translate_interface(index)?,
+				_ => {}
+			}
+		}
+
+		Ok(compiler)
+	}
+
+	pub fn error(&self, node: usize, pos: usize, message: impl Display) -> anyhow::Error {
+		let file = &self.files[self.nodes[node].file];
+		anyhow!("{}: {message}", file.location(pos))
+	}
+
+	pub fn node_by_id(&self, id: u64) -> Option<&Node<'a>> {
+		self.by_id.get(&id).map(|index| &self.nodes[*index])
+	}
+
+	fn push(&mut self, node: Node<'a>, pos: usize) -> Result<usize> {
+		let index = self.nodes.len();
+		if let Some(existing) = self.by_id.insert(node.id, index) {
+			let name = &self.nodes[existing].display_name;
+			let file = &self.files[node.file];
+			return Err(anyhow!(
+				"{}: {} has the same ID as {name}",
+				file.location(pos),
+				node.display_name
+			));
+		}
+
+		self.nodes.push(node);
+		Ok(index)
+	}
+
+	fn declare_file(&mut self, file: usize) -> Result<()> {
+		let source = &self.files[file];
+		let parsed = &source.parsed;
+		let id = parsed.id.ok_or_else(|| {
+			anyhow!(
+				"{}: file has no ID; add a line like `@0x{:016x};`",
+				source.name,
+				1u64 << 63
+			)
+		})?;
+
+		let node = Node {
+			id,
+			display_name: source.name.clone(),
+			prefix_len: source.name.rfind('/').map_or(0, |slash| slash + 1),
+			scope_id: 0,
+			parent: None,
+			file,
+			params: &[],
+			is_generic: false,
+			nested: vec![],
+			members: HashMap::new(),
+			doc: parsed.doc.as_deref(),
+			annotations: &parsed.annotations,
+			kind: Kind::File,
+			decl: None,
+		};
+
+		let index = self.push(node, 0)?;
+		self.file_nodes.push(index);
+		self.declare_members(index, &parsed.decls)
+	}
+
+	fn declare_members(&mut self, parent: usize, decls: &'a [Decl]) -> Result<()> {
+		for decl in decls {
+			let member = match &decl.kind {
+				DeclKind::Using(target) => Member::Alias(target),
+				DeclKind::Struct { .. }
+				| DeclKind::Enum
+				| DeclKind::Interface { .. }
+				| DeclKind::Const { .. }
+				| DeclKind::Annotation { .. } => {
+					let index = self.declare(parent, decl)?;
+					self.nodes[parent].nested.push(index);
+					Member::Node(index)
+				}
+				_ => continue,
+			};
+
+			if self.nodes[parent].members.insert(&decl.name, member).is_some() {
+				return Err(self.error(parent, decl.pos, format!("'{}' is already defined", decl.name)));
+			}
+		}
+
+		Ok(())
+	}
+
+	fn declare(&mut self, parent: usize, decl: &'a Decl) -> Result<usize> {
+		let scope = &self.nodes[parent];
+		let id = match decl.number {
+			Some((id, _)) if id & (1 << 63) != 0 => id,
+			Some((_, pos)) => {
+				return Err(self.error(parent, pos, "invalid ID; IDs must have the high bit set"))
+			}
+			None => child_id(scope.id, &decl.name),
+		};
+
+		let separator = if scope.parent.is_none() { ':' } else { '.' };
+		let display_name = format!("{}{separator}{}", scope.display_name, decl.name);
+
+		let params: &[String] = match &decl.kind {
+			DeclKind::Struct { params } | DeclKind::Interface { params, .. } => params,
+			_ => &[],
+		};
+
+		let kind = match &decl.kind {
+			DeclKind::Enum => Kind::Enum(self.enumerants(parent, decl)?),
+			DeclKind::Interface { extends, .. } => Kind::Interface {
+				extends,
+				methods: vec![],
+			},
+			DeclKind::Const { type_, value } => Kind::Const { type_, value },
+			DeclKind::Annotation { targets, type_ } => Kind::Annotation { targets, type_ },
+			_ => Kind::Struct(StructDef::default()),
+		};
+
+		let node = Node {
+			id,
+			prefix_len: display_name.len() - decl.name.len(),
+			display_name,
+			scope_id: scope.id,
+			parent: Some(parent),
+			file: scope.file,
+			params,
+			is_generic: scope.is_generic || !params.is_empty(),
+			nested: vec![],
+			members: HashMap::new(),
+			doc: decl.doc.as_deref(),
+			annotations: &decl.annotations,
+			kind,
+			decl: Some(decl),
+		};
+
+		let index = self.push(node, decl.pos)?;
+		if matches!(decl.kind, DeclKind::Struct { .. } | DeclKind::Interface { .. }) {
+			self.declare_members(index, &decl.nested)?;
+		}
+
+		Ok(index)
+	}
+
+	fn enumerants(&self, parent: usize, decl: &'a Decl) -> Result<Vec<(u16, &'a Decl)>> {
+		let mut enumerants = vec![];
+		for (code_order, enumerant) in decl.nested.iter().enumerate() {
+			let Some((ordinal, _)) = enumerant.number else {
+				return Err(self.error(parent, enumerant.pos, "enumerant needs an ordinal"));
+			};
+			enumerants.push((ordinal, code_order as u16, enumerant));
+		}
+
+		enumerants.sort_by_key(|(ordinal, ..)| *ordinal);
+		for (expected, (ordinal, _, enumerant)) in enumerants.iter().enumerate() {
+			if *ordinal != expected as u64 {
+				return Err(self.error(parent, enumerant.pos, ordinal_error(expected)));
+			}
+		}
+
+		Ok(enumerants
+			.into_iter()
+			.map(|(_, code_order, enumerant)| (code_order, enumerant))
+			.collect())
+	}
+
+	fn translate_struct(&mut self, index: usize) -> Result<()> {
+		let Some(decl) = self.nodes[index].decl else {
+			return Ok(());
+		};
+
+		let mut translator = StructTranslator::new(self, index, Lookup::new(index));
+		translator.traverse(&decl.nested, 0, Container::Top)?;
+		let (def, groups) = translator.finish()?;
+
+		self.nodes[index].kind = Kind::Struct(def);
+		for group in groups {
+			let node = Node {
+				id: group.id,
+				prefix_len: group.display_name.len() - group.name.len(),
+				display_name: group.display_name,
+				scope_id: group.scope_id,
+				parent: Some(index),
+				file: self.nodes[index].file,
+				params: &[],
+				is_generic: self.nodes[index].is_generic,
+				nested: vec![],
+				members: HashMap::new(),
+				doc: group.doc,
+				annotations: group.annotations,
+				kind: Kind::Struct(group.def),
+				decl: None,
+			};
+			self.push(node, decl.pos)?;
+		}
+
+		Ok(())
+	}
+
+	fn translate_interface(&mut self, index: usize) -> Result<()> {
+		let Some(decl) = self.nodes[index].decl else {
+			return Ok(());
+		};
+
+		let mut methods = vec![];
+		for nested in &decl.nested {
+			if let DeclKind::Method { .. } = nested.kind {
+				let Some((ordinal, _)) = nested.number else {
+					return Err(self.error(index, nested.pos, "method needs an ordinal"));
+				};
+				methods.push((ordinal, methods.len() as u16, nested));
+			}
+		}
+
+		methods.sort_by_key(|(ordinal, ..)| *ordinal);
+		let mut defs = vec![];
+		for (expected, (ordinal, code_order, method)) in methods.into_iter().enumerate() {
+			if ordinal != expected as u64 {
+				return Err(self.error(index, method.pos, ordinal_error(expected)));
+			}
+			let DeclKind::Method {
+				implicit,
+				params,
+				results,
+			} = &method.kind
+			else {
+				continue;
+			};
+
+			defs.push(MethodDef {
+				decl: method,
+				code_order,
+				implicit,
+				params: self.params(index, method, ordinal as u16, implicit, params, false)?,
+				results: self.params(index, method, ordinal as u16, implicit, results, true)?,
+			});
+		}
+
+		if let Kind::Interface { methods, .. } = &mut self.nodes[index].kind {
+			*methods = defs;
+		}
+
+		Ok(())
+	}
+
+	fn params(
+		&mut self,
+		interface: usize,
+		method: &'a Decl,
+		ordinal: u16,
+		implicit: &'a [String],
+		list: &'a ParamList,
+		is_results: bool,
+	) -> Result<Params<'a>> {
+		let (params, pos) = match list {
+			ParamList::Type(type_) => return Ok(Params::Type(type_)),
+			ParamList::Stream(pos) => return Ok(Params::Stream(*pos)),
+			ParamList::Fields(params, pos) => (params, *pos),
+		};
+
+		let scope = &self.nodes[interface];
+		let id = method_params_id(scope.id, ordinal, is_results);
+		let name = format!(
+			"{}${}",
+			method.name,
+			if is_results { "Results" } else { "Params" }
+		);
+		let display_name = format!("{}.{name}", scope.display_name);
+		let lookup = Lookup {
+			node: interface,
+			implicit,
+			params_struct: Some(id),
+		};
+
+		let mut translator = StructTranslator::new(self, interface, lookup);
+		translator.params(params)?;
+		let (def, _) = translator.finish()?;
+
+		let node = Node {
+			id,
+			prefix_len: display_name.len() - name.len(),
+			display_name,
+			// the struct isn't nested in anything, so it has no scope
+			scope_id: 0,
+			parent: Some(interface),
+			file: scope.file,
+			params: implicit,
+			is_generic: scope.is_generic || !implicit.is_empty(),
+			nested: vec![],
+			members: HashMap::new(),
+			doc: None,
+			annotations: &[],
+			kind: Kind::Struct(def),
+			decl: None,
+		};
+		self.push(node, pos)?;
+
+		Ok(Params::Struct(id))
+	}
+
+	/// Resolves `expr` to a type.
+	pub fn type_of(&self, expr: &Expr, lookup: Lookup<'a>) -> Result<TypeRef> {
+		match self.resolve(expr, lookup)? {
+			Entity::Type(type_) => Ok(type_),
+			Entity::List => Err(self.error(lookup.node, expr.pos, "'List' needs an element type")),
+			Entity::Node(index, bindings) => {
+				let node = &self.nodes[index];
+				let brand = self.brand(index, &bindings, lookup);
+				match node.kind {
+					Kind::Struct(_) => Ok(TypeRef::Struct(node.id, brand)),
+					Kind::Enum(_) => Ok(TypeRef::Enum(node.id, brand)),
+					Kind::Interface { .. } => Ok(TypeRef::Interface(node.id, brand)),
+					_ => Err(self.error(
+						lookup.node,
+						expr.pos,
+						format!("'{}' is not a type", node.display_name),
+					)),
+				}
+			}
+		}
+	}
+
+	/// Resolves `expr` to a declaration, such as a const or an annotation.
+	pub fn node_of(&self, expr: &Expr, lookup: Lookup<'a>) -> Result<usize> {
+		match self.resolve(expr, lookup)? {
+			Entity::Node(index, _) => Ok(index),
+			_ => Err(self.error(lookup.node, expr.pos, "expected the name of a declaration")),
+		}
+	}
+
+	fn resolve(&self, expr: &Expr, lookup: Lookup<'a>) -> Result<Entity> {
+		match &expr.kind {
+			ExprKind::Name(name) => self
+				.lookup_name(name, lookup)?
+				.ok_or_else(|| self.error(lookup.node, expr.pos, format!("'{name}' is not defined"))),
+			ExprKind::Absolute(name) => {
+				let file = self.file_nodes[self.nodes[lookup.node].file];
+				self.member(file, name, vec![], expr.pos, lookup)
+			}
+			ExprKind::Import(path) => {
+				let file = &self.files[self.nodes[lookup.node].file];
+				match file.imports.iter().find(|(name, _)| name == path) {
+					Some((_, imported)) => Ok(Entity::Node(self.file_nodes[*imported], vec![])),
+					None => {
+						Err(self.error(lookup.node, expr.pos, format!("import \"{path}\" was not loaded")))
+					}
+				}
+			}
+			ExprKind::Member(parent, name) => match self.resolve(parent, lookup)? {
+				Entity::Node(index, bindings) => self.member(index, name, bindings, expr.pos, lookup),
+				_ => Err(self.error(
+					lookup.node,
+					expr.pos,
+					format!("built-in types have no member '{name}'"),
+				)),
+			},
+			ExprKind::Apply(target, args) => {
+				let mut types = vec![];
+				for arg in args {
+					if arg.name.is_some() {
+						return Err(self.error(
+							lookup.node,
+							arg.value.pos,
+							"generic arguments can't be named",
+						));
+					}
+					types.push(self.type_of(&arg.value, lookup)?);
+				}
+
+				match self.resolve(target, lookup)? {
+					Entity::List if types.len() == 1 => {
+						Ok(Entity::Type(TypeRef::List(Box::new(types.remove(0)))))
+					}
+					Entity::Node(index, mut bindings) if self.nodes[index].params.len() == types.len() => {
+						let value_type = args
+							.iter()
+							.zip(&types)
+							.find(|(_, type_)| !matches!(type_.size(), Size::Pointer));
+						if let Some((arg, _)) = value_type {
+							return Err(self.error(
+								lookup.node,
+								arg.value.pos,
+								"only pointer types can be generic arguments",
+							));
+						}
+						bindings.push((index, types));
+						Ok(Entity::Node(index, bindings))
+					}
+					_ => Err(self.error(lookup.node, expr.pos, "wrong number of generic arguments")),
+				}
+			}
+			_ => Err(self.error(lookup.node, expr.pos, "expected a name")),
+		}
+	}
+
+	fn lookup_name(&self, name: &str, lookup: Lookup<'a>) -> Result<Option<Entity>> {
+		if let Some(index) = lookup.implicit.iter().position(|param| param == name) {
+			let index = index as u16;
+			let type_ = match lookup.params_struct {
+				Some(scope_id) => TypeRef::Param { scope_id, index },
+				None => TypeRef::ImplicitParam(index),
+			};
+			return Ok(Some(Entity::Type(type_)));
+		}
+
+		let mut current = Some(lookup.node);
+		while let Some(index) = current {
+			let node = &self.nodes[index];
+			if let Some(member) = node.members.get(name) {
+				return self.member_entity(index, member, vec![]).map(Some);
+			}
+			if let Some(param) = node.params.iter().position(|param| param == name) {
+				return Ok(Some(Entity::Type(TypeRef::Param {
+					scope_id: node.id,
+					index: param as u16,
+				})));
+			}
+			current = node.parent;
+		}
+
+		let builtin = match name {
+			"Void" => TypeRef::Void,
+			"Bool" => TypeRef::Bool,
+			"Int8" => TypeRef::Int8,
+			"Int16" => TypeRef::Int16,
+			"Int32" => TypeRef::Int32,
+			"Int64" => TypeRef::Int64,
+			"UInt8" => TypeRef::Uint8,
+			"UInt16" => TypeRef::Uint16,
+			"UInt32" => TypeRef::Uint32,
+			"UInt64" => TypeRef::Uint64,
+			"Float32" => TypeRef::Float32,
+			"Float64" => TypeRef::Float64,
+			"Text" => TypeRef::Text,
+			"Data" => TypeRef::Data,
+			"AnyPointer" => TypeRef::AnyPointer,
+			"AnyStruct" => TypeRef::AnyStruct,
+			"AnyList" => TypeRef::AnyList,
+			"Capability" => TypeRef::Capability,
+			"List" => return Ok(Some(Entity::List)),
+			_ => return Ok(None),
+		};
+
+		Ok(Some(Entity::Type(builtin)))
+	}
+
+	fn member(
+		&self,
+		index: usize,
+		name: &str,
+		bindings: Vec<(usize, Vec<TypeRef>)>,
+		pos: usize,
+		lookup: Lookup<'a>,
+	) -> Result<Entity> {
+		match self.nodes[index].members.get(name) {
+			Some(member) => self.member_entity(index, member, bindings),
+			None => Err(self.error(
+				lookup.node,
+				pos,
+				format!(
+					"'{}' has no member named '{name}'",
+					self.nodes[index].display_name
+				),
+			)),
+		}
+	}
+
+	fn member_entity(
+		&self,
+		owner: usize,
+		member: &Member<'a>,
+		bindings: Vec<(usize, Vec<TypeRef>)>,
+	) -> Result<Entity> {
+		match member {
+			Member::Node(index) => Ok(Entity::Node(*index, bindings)),
+			// aliases are resolved where they were declared
+			Member::Alias(target) => self.resolve(target, Lookup::new(owner)),
+		}
+	}
+
+	/// The brand for a reference to `index`: explicit arguments where given, and the
+	/// enclosing scope's own parameters for generic scopes the reference is made from inside.
+	pub fn brand(&self, index: usize, bindings: &[(usize, Vec<TypeRef>)], lookup: Lookup<'a>) -> Brand {
+		let mut lexical = vec![];
+		let mut current = Some(lookup.node);
+		while let Some(scope) = current {
+			lexical.push(scope);
+			current = self.nodes[scope].parent;
+		}
+
+		let mut brand = vec![];
+		let mut current = Some(index);
+		while let Some(scope) = current {
+			let node = &self.nodes[scope];
+			if !node.params.is_empty() {
+				match bindings.iter().find(|(bound, _)| *bound == scope) {
+					Some((_, types)) => brand.push((node.id, Some(types.clone()))),
+					None if lexical.contains(&scope) => brand.push((node.id, None)),
+					None => {}
+				}
+			}
+			current = node.parent;
+		}
+
+		brand
+	}
+}
+
+fn ordinal_error(expected: usize) -> String {
+	format!("skipped ordinal @{expected}; ordinals must be sequential with no holes")
+}
+
+/// A group node found while translating a struct.
+struct GroupNode<'a> {
+	id: u64,
+	name: &'a str,
+	display_name: String,
+	scope_id: u64,
+	doc: Option<&'a str>,
+	annotations: &'a [Application],
+	def: StructDef<'a>,
+}
+
+enum MemberKind<'a> {
+	/// The struct itself.
+	Root,
+	Field {
+		type_: TypeRef,
+		default: Option<&'a Expr>,
+		container: Container,
+	},
+	Group,
+}
+
+/// A field or group, tracked while the struct is laid out.
+struct MemberInfo<'a> {
+	parent: usize,
+	code_order: u16,
+	in_union: bool,
+	name: &'a str,
+	ordinal: Option<u16>,
+	doc: Option<&'a str>,
+	annotations: &'a [Application],
+	kind: MemberKind<'a>,
+	/// The union of a named union, or the unnamed union in a struct or group.
+	union: Option<usize>,
+	display_name: String,
+	id: u64,
+
+	/// Set once the member is given its place in its parent's field list.
+	index: Option<u16>,
+	discriminant: Option<u16>,
+	children: Vec<usize>,
+	discriminants: u16,
+	offset: u32,
+}
+
+enum Step {
+	Field(usize),
+	/// A union given an explicit ordinal, which places its discriminant.
+	Union(usize, usize),
+}
+
+/// Lays out one struct, following capnp's node translator: members are gathered in code
+/// order, then given offsets in ordinal order.
+struct StructTranslator<'c, 'a> {
+	compiler: &'c Compiler<'a>,
+	node: usize,
+	lookup: Lookup<'a>,
+	layout: Layout,
+	members: Vec<MemberInfo<'a>>,
+	steps: Vec<(u64, usize, Step)>,
+}
+
+impl<'c, 'a> StructTranslator<'c, 'a> {
+	fn new(compiler: &'c Compiler<'a>, node: usize, lookup: Lookup<'a>) -> Self {
+		let root = MemberInfo {
+			parent: 0,
+			code_order: 0,
+			in_union: false,
+			name: "",
+			ordinal: None,
+			doc: None,
+			annotations: &[],
+			kind: MemberKind::Root,
+			union: None,
+			display_name: compiler.nodes[node].display_name.clone(),
+			id: compiler.nodes[node].id,
+			index: None,
+			discriminant: None,
+			children: vec![],
+			discriminants: 0,
+			offset: 0,
+		};
+
+		StructTranslator {
+			compiler,
+			node,
+			lookup,
+			layout: Layout::default(),
+			members: vec![root],
+			steps: vec![],
+		}
+	}
+
+	fn add(
+		&mut self,
+		parent: usize,
+		code_order: u16,
+		decl: &'a Decl,
+		kind: MemberKind<'a>,
+		in_union: bool,
+	) -> usize {
+		let display_name = format!("{}.{}", self.members[parent].display_name, decl.name);
+		self.members.push(MemberInfo {
+			parent,
+			code_order,
+			in_union,
+			name: &decl.name,
+			ordinal: None,
+			doc: decl.doc.as_deref(),
+			annotations: &decl.annotations,
+			kind,
+			union: None,
+			display_name,
+			id: 0,
+			index: None,
+			discriminant: None,
+			children: vec![],
+			discriminants: 0,
+			offset: 0,
+		});
+		self.members.len() - 1
+	}
+
+	fn field(
+		&mut self,
+		parent: usize,
+		code_order: u16,
+		decl: &'a Decl,
+		container: Container,
+		in_union: bool,
+	) -> Result<()> {
+		let DeclKind::Field { type_, default } = &decl.kind else {
+			return Ok(());
+		};
+		let Some((ordinal, pos)) = decl.number else {
+			return Err(self.compiler.error(self.node, decl.pos, "field needs an ordinal"));
+		};
+
+		let kind = MemberKind::Field {
+			type_: self.compiler.type_of(type_, self.lookup)?,
+			default: default.as_ref(),
+			container,
+		};
+		let member = self.add(parent, code_order, decl, kind, in_union);
+		self.members[member].ordinal = Some(ordinal as u16);
+		self.steps.push((ordinal, pos, Step::Field(member)));
+
+		Ok(())
+	}
+
+	fn traverse(&mut self, decls: &'a [Decl], parent: usize, container: Container) -> Result<()> {
+		let mut code_order = 0;
+
+		for decl in decls {
+			match decl.kind {
+				DeclKind::Field { .. } => {
+					self.field(parent, code_order, decl, container, false)?;
+					code_order += 1;
+				}
+				DeclKind::Union if decl.name.is_empty() => {
+					// an unnamed union's members belong to the enclosing struct or group
+					let union = self.layout.new_union(container);
+					self.members[parent].union = Some(union);
+					self.traverse_union(decl, parent, union, &mut code_order)?;
+					self.union_ordinal(decl, union);
+				}
+				DeclKind::Union => {
+					let member = self.add(parent, code_order, decl, MemberKind::Group, false);
+					code_order += 1;
+					self.named_union(decl, member, container)?;
+				}
+				DeclKind::Group => {
+					let member = self.add(parent, code_order, decl, MemberKind::Group, false);
+					code_order += 1;
+					self.traverse_group(decl, member, container)?;
+				}
+				_ => {}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn traverse_group(&mut self, decl: &'a Decl, member: usize, container: Container) -> Result<()> {
+		if decl.nested.is_empty() {
+			return Err(self
+				.compiler
+				.error(self.node, decl.pos, "group must have at least one member"));
+		}
+
+		self.traverse(&decl.nested, member, container)
+	}
+
+	/// A named union, which is a group holding nothing but an unnamed union.
+	fn named_union(&mut self, decl: &'a Decl, member: usize, container: Container) -> Result<()> {
+		let union = self.layout.new_union(container);
+		self.members[member].union = Some(union);
+		self.traverse_union(decl, member, union, &mut 0)?;
+		self.union_ordinal(decl, union);
+
+		Ok(())
+	}
+
+	fn union_ordinal(&mut self, decl: &'a Decl, union: usize) {
+		if let Some((ordinal, pos)) = decl.number {
+			self.steps.push((ordinal, pos, Step::Union(union, decl.pos)));
+		}
+	}
+
+	fn traverse_union(
+		&mut self,
+		decl: &'a Decl,
+		parent: usize,
+		union: usize,
+		code_order: &mut u16,
+	) -> Result<()> {
+		if decl.nested.len() < 2 {
+			return Err(self
+				.compiler
+				.error(self.node, decl.pos, "union must have at least two members"));
+		}
+
+		for member in &decl.nested {
+			match member.kind {
+				DeclKind::Field { .. } => {
+					// each field is laid out as if it were a group of one
+					let group = self.layout.new_group(union);
+					self.field(parent, *code_order, member, group, true)?;
+					*code_order += 1;
+				}
+				DeclKind::Group => {
+					let group = self.layout.new_group(union);
+					let index = self.add(parent, *code_order, member, MemberKind::Group, true);
+					*code_order += 1;
+					self.traverse_group(member, index, group)?;
+				}
+				DeclKind::Union if member.name.is_empty() => {
+					return Err(self.compiler.error(
+						self.node,
+						member.pos,
+						"unions cannot contain unnamed unions",
+					));
+				}
+				DeclKind::Union => {
+					let group = self.layout.new_group(union);
+					let index = self.add(parent, *code_order, member, MemberKind::Group, true);
+					*code_order += 1;
+					self.named_union(member, index, group)?;
+				}
+				_ => {}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Fields of a method's inline param list, which are ordered as written.
+	fn params(&mut self, params: &'a [MethodParam]) -> Result<()> {
+		for (i, param) in params.iter().enumerate() {
+			let kind = MemberKind::Field {
+				type_: self.compiler.type_of(&param.type_, self.lookup)?,
+				default: param.default.as_ref(),
+				container: Container::Top,
+			};
+			let display_name = format!("{}.{}", self.members[0].display_name, param.name);
+			self.members.push(MemberInfo {
+				parent: 0,
+				code_order: i as u16,
+				in_union: false,
+				name: &param.name,
+				ordinal: None,
+				doc: None,
+				annotations: &param.annotations,
+				kind,
+				union: None,
+				display_name,
+				id: 0,
+				index: None,
+				discriminant: None,
+				children: vec![],
+				discriminants: 0,
+				offset: 0,
+			});
+			self.steps
+				.push((i as u64, param.pos, Step::Field(self.members.len() - 1)));
+		}
+
+		Ok(())
+	}
+
+	/// Gives `member` its place in its parent's field list, the first time it is used.
+	fn touch(&mut self, member: usize) {
+		if member == 0 || self.members[member].index.is_some() {
+			return;
+		}
+
+		let parent = self.members[member].parent;
+		if self.members[parent].children.is_empty() {
+			self.touch(parent);
+		}
+
+		let index = self.members[parent].children.len() as u16;
+		self.members[parent].children.push(member);
+		self.members[member].index = Some(index);
+
+		if self.members[member].in_union {
+			self.members[member].discriminant = Some(self.members[parent].discriminants);
+			self.members[parent].discriminants += 1;
+		}
+	}
+
+	fn finish(mut self) -> Result<(StructDef<'a>, Vec<GroupNode<'a>>)> {
+		let mut steps = std::mem::take(&mut self.steps);
+		steps.sort_by_key(|(ordinal, ..)| *ordinal);
+
+		for (expected, (ordinal, pos, step)) in steps.into_iter().enumerate() {
+			if ordinal < expected as u64 {
+				return Err(self
+					.compiler
+					.error(self.node, pos, format!("duplicate ordinal @{ordinal}")));
+			}
+			if ordinal > expected as u64 {
+				return Err(self.compiler.error(self.node, pos, ordinal_error(expected)));
+			}
+
+			match step {
+				Step::Field(member) => {
+					self.touch(member);
+					if let MemberKind::Field { type_, container, .. } = &self.members[member].kind {
+						let container = *container;
+						self.members[member].offset = match type_.size() {
+							Size::Void => {
+								self.layout.add_void(container);
+								0
+							}
+							Size::Data(lg_size) => self.layout.add_data(container, lg_size),
+							Size::Pointer => self.layout.add_pointer(container),
+						};
+					}
+				}
+				Step::Union(union, pos) => {
+					if !self.layout.add_discriminant(union) {
+						return Err(self.compiler.error(
+							self.node,
+							pos,
+							"a union's ordinal can't be above more than one of its members' ordinals",
+						));
+					}
+				}
+			}
+		}
+
+		// unions that never had two members in use still get a discriminant
+		for member in 0..self.members.len() {
+			if let Some(union) = self.members[member].union {
+				self.layout.add_discriminant(union);
+			}
+			if let MemberKind::Group = self.members[member].kind {
+				self.touch(member);
+				let parent = self.members[member].parent;
+				let index = self.members[member].index.unwrap_or_default();
+				self.members[member].id = group_id(self.members[parent].id, index);
+			}
+		}
+
+		let root = self.def(0);
+		let mut groups = vec![];
+		for member in 1..self.members.len() {
+			if let MemberKind::Group = self.members[member].kind {
+				let info = &self.members[member];
+				groups.push(GroupNode {
+					id: info.id,
+					name: info.name,
+					display_name: info.display_name.clone(),
+					scope_id: self.members[info.parent].id,
+					doc: None,
+					annotations: &[],
+					def: self.def(member),
+				});
+			}
+		}
+
+		Ok((root, groups))
+	}
+
+	fn def(&self, member: usize) -> StructDef<'a> {
+		let info = &self.members[member];
+		let fields = info
+			.children
+			.iter()
+			.map(|child| {
+				let child = &self.members[*child];
+				let kind = match &child.kind {
+					MemberKind::Field { type_, default, .. } => FieldKind::Slot {
+						type_: type_.clone(),
+						offset: child.offset,
+						default: *default,
+					},
+					_ => FieldKind::Group(child.id),
+				};
+
+				FieldDef {
+					name: child.name,
+					code_order: child.code_order,
+					discriminant: child.discriminant,
+					ordinal: child.ordinal,
+					annotations: child.annotations,
+					doc: child.doc,
+					kind,
+				}
+			})
+			.collect();
+
+		StructDef {
+			is_group: member != 0,
+			data_words: self.layout.data_words as u16,
+			pointers: self.layout.pointers as u16,
+			discriminant_count: info.discriminants,
+			discriminant_offset: info
+				.union
+				.and_then(|union| self.layout.discriminant_offset(union))
+				.unwrap_or_default(),
+			fields,
+			lookup: self.lookup,
+		}
+	}
+}
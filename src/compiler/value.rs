@@ -0,0 +1,466 @@
+//! Evaluates constant expressions against their type and encodes them.
+
+use super::parser::{Expr, ExprKind, Param};
+use super::translate::{Brand, Compiler, FieldKind, Kind, Lookup, Size, StructDef, TypeRef};
+use anyhow::Result;
+use capnp::private::layout::{
+	ElementSize, ListBuilder, PointerBuilder, PrimitiveElement, StructBuilder, StructSize,
+};
+use capnp::traits::FromPointerBuilder;
+use capnpc::schema_capnp::value;
+
+/// Consts referring to consts stop here, which only a cycle would reach.
+const MAX_DEPTH: u32 = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	Void,
+	Bool(bool),
+	Int8(i8),
+	Int16(i16),
+	Int32(i32),
+	Int64(i64),
+	Uint8(u8),
+	Uint16(u16),
+	Uint32(u32),
+	Uint64(u64),
+	Float32(f32),
+	Float64(f64),
+	Text(String),
+	Data(Vec<u8>),
+	Enum(u16),
+	List(Vec<Value>),
+	/// The struct (or group) node, the brand it is used with, and values for some of its
+	/// fields by index.
+	Struct(u64, Brand, Vec<(usize, Value)>),
+	/// An unset pointer.
+	Null,
+}
+
+impl Value {
+	/// The value a field of this type has when it has no explicit default.
+	pub fn zero(type_: &TypeRef) -> Value {
+		match type_ {
+			TypeRef::Void => Value::Void,
+			TypeRef::Bool => Value::Bool(false),
+			TypeRef::Int8 => Value::Int8(0),
+			TypeRef::Int16 => Value::Int16(0),
+			TypeRef::Int32 => Value::Int32(0),
+			TypeRef::Int64 => Value::Int64(0),
+			TypeRef::Uint8 => Value::Uint8(0),
+			TypeRef::Uint16 => Value::Uint16(0),
+			TypeRef::Uint32 => Value::Uint32(0),
+			TypeRef::Uint64 => Value::Uint64(0),
+			TypeRef::Float32 => Value::Float32(0.0),
+			TypeRef::Float64 => Value::Float64(0.0),
+			TypeRef::Enum(..) => Value::Enum(0),
+			_ => Value::Null,
+		}
+	}
+}
+
+/// The untyped pointer behind an `AnyPointer`, so values can be written into it directly.
+struct RawPointer<'a>(PointerBuilder<'a>);
+
+impl<'a> FromPointerBuilder<'a> for RawPointer<'a> {
+	fn init_pointer(builder: PointerBuilder<'a>, _length: u32) -> Self {
+		RawPointer(builder)
+	}
+
+	fn get_from_pointer(
+		builder: PointerBuilder<'a>,
+		_default: Option<&'a [capnp::Word]>,
+	) -> capnp::Result<Self> {
+		Ok(RawPointer(builder))
+	}
+}
+
+impl<'a> Compiler<'a> {
+	pub fn eval(&self, expr: &Expr, type_: &TypeRef, lookup: Lookup<'a>) -> Result<Value> {
+		self.eval_at(expr, type_, lookup, 0)
+	}
+
+	fn eval_at(&self, expr: &Expr, type_: &TypeRef, lookup: Lookup<'a>, depth: u32) -> Result<Value> {
+		let mismatch = || {
+			self.error(
+				lookup.node,
+				expr.pos,
+				format!("value doesn't match the type {type_}"),
+			)
+		};
+
+		if let ExprKind::Name(_) | ExprKind::Member(..) | ExprKind::Absolute(_) = expr.kind {
+			return match self.named(expr, type_, lookup)? {
+				Some(value) => Ok(value),
+				None => self.const_value(expr, type_, lookup, depth),
+			};
+		}
+
+		let value = match (type_, &expr.kind) {
+			(TypeRef::Void, ExprKind::Tuple(params)) if params.is_empty() => Value::Void,
+			(_, ExprKind::Int(value)) => int(type_, i128::from(*value)).ok_or_else(mismatch)?,
+			(_, ExprKind::NegativeInt(value)) => int(type_, -i128::from(*value)).ok_or_else(mismatch)?,
+			(TypeRef::Float32, ExprKind::Float(value)) => Value::Float32(*value as f32),
+			(TypeRef::Float64, ExprKind::Float(value)) => Value::Float64(*value),
+			(TypeRef::Text, ExprKind::Str(text)) => Value::Text(text.clone()),
+			(TypeRef::Data, ExprKind::Str(text)) => Value::Data(text.as_bytes().to_vec()),
+			(TypeRef::Data, ExprKind::Bytes(data)) => Value::Data(data.clone()),
+			(TypeRef::Text, ExprKind::Embed(path)) => {
+				let data = self.embed(expr, path, lookup)?;
+				let text = String::from_utf8(data)
+					.map_err(|_| self.error(lookup.node, expr.pos, format!("\"{path}\" is not UTF-8")))?;
+				Value::Text(text)
+			}
+			(TypeRef::Data, ExprKind::Embed(path)) => Value::Data(self.embed(expr, path, lookup)?),
+			(TypeRef::List(element), ExprKind::List(items)) => Value::List(
+				items
+					.iter()
+					.map(|item| self.eval_at(item, element, lookup, depth))
+					.collect::<Result<_>>()?,
+			),
+			(TypeRef::Struct(id, brand), ExprKind::Tuple(params)) => {
+				self.struct_value(*id, brand, params, lookup, depth)?
+			}
+			_ => return Err(mismatch()),
+		};
+
+		Ok(value)
+	}
+
+	/// Names that stand for a value of the type rather than a const: `true`, `inf`, `void`
+	/// and enumerants.
+	fn named(&self, expr: &Expr, type_: &TypeRef, lookup: Lookup<'a>) -> Result<Option<Value>> {
+		let ExprKind::Name(name) = &expr.kind else {
+			return Ok(None);
+		};
+
+		let value = match (type_, name.as_str()) {
+			(TypeRef::Void, "void") => Value::Void,
+			(TypeRef::Bool, "true") => Value::Bool(true),
+			(TypeRef::Bool, "false") => Value::Bool(false),
+			(TypeRef::Float32, "inf") => Value::Float32(f32::INFINITY),
+			(TypeRef::Float64, "inf") => Value::Float64(f64::INFINITY),
+			(TypeRef::Float32, "nan") => Value::Float32(f32::NAN),
+			(TypeRef::Float64, "nan") => Value::Float64(f64::NAN),
+			(TypeRef::Enum(id, _), _) => {
+				let Some(Kind::Enum(enumerants)) = self.node_by_id(*id).map(|node| &node.kind) else {
+					return Ok(None);
+				};
+				match enumerants
+					.iter()
+					.position(|(_, enumerant)| enumerant.name == *name)
+				{
+					Some(ordinal) => Value::Enum(ordinal as u16),
+					None => {
+						return Err(self.error(
+							lookup.node,
+							expr.pos,
+							format!("'{name}' is not an enumerant"),
+						));
+					}
+				}
+			}
+			_ => return Ok(None),
+		};
+
+		Ok(Some(value))
+	}
+
+	fn const_value(&self, expr: &Expr, type_: &TypeRef, lookup: Lookup<'a>, depth: u32) -> Result<Value> {
+		if depth > MAX_DEPTH {
+			return Err(self.error(lookup.node, expr.pos, "const refers to itself"));
+		}
+
+		let index = self.node_of(expr, lookup)?;
+		match self.nodes[index].kind {
+			Kind::Const { value, .. } => self.eval_at(value, type_, Lookup::new(index), depth + 1),
+			_ => Err(self.error(
+				lookup.node,
+				expr.pos,
+				format!("'{}' is not a const", self.nodes[index].display_name),
+			)),
+		}
+	}
+
+	fn struct_value(
+		&self,
+		id: u64,
+		brand: &Brand,
+		params: &[Param],
+		lookup: Lookup<'a>,
+		depth: u32,
+	) -> Result<Value> {
+		let def = self.struct_def(id)?;
+
+		let mut fields = vec![];
+		for param in params {
+			let pos = param.value.pos;
+			let Some(name) = &param.name else {
+				return Err(self.error(
+					lookup.node,
+					pos,
+					"struct values need field names, e.g. `(name = value)`",
+				));
+			};
+			let Some(index) = def.fields.iter().position(|field| field.name == name) else {
+				return Err(self.error(lookup.node, pos, format!("no field named '{name}'")));
+			};
+
+			let value = match (&def.fields[index].kind, &param.value.kind) {
+				(FieldKind::Slot { type_, .. }, _) => {
+					self.eval_at(&param.value, &type_.substitute(brand), lookup, depth)?
+				}
+				(FieldKind::Group(group), ExprKind::Tuple(params)) => {
+					self.struct_value(*group, brand, params, lookup, depth)?
+				}
+				(FieldKind::Group(_), _) => {
+					return Err(self.error(
+						lookup.node,
+						pos,
+						format!("'{name}' is a group, so needs a struct value"),
+					));
+				}
+			};
+			fields.push((index, value));
+		}
+
+		Ok(Value::Struct(id, brand.clone(), fields))
+	}
+
+	fn embed(&self, expr: &Expr, path: &str, lookup: Lookup<'a>) -> Result<Vec<u8>> {
+		let file = &self.files[self.nodes[lookup.node].file];
+
+		match file.embeds.iter().find(|(embedded, _)| embedded == path) {
+			Some((_, data)) => Ok(data.clone()),
+			None => Err(self.error(lookup.node, expr.pos, format!("\"{path}\" was not loaded"))),
+		}
+	}
+
+	pub fn struct_def(&self, id: u64) -> Result<&StructDef<'a>> {
+		match self.node_by_id(id).map(|node| &node.kind) {
+			Some(Kind::Struct(def)) => Ok(def),
+			_ => Err(anyhow::anyhow!("node {id:#018x} is not a struct")),
+		}
+	}
+
+	/// Stores `value` in a schema `Value`, such as a const or a field's default.
+	pub fn write_value(&self, mut builder: value::Builder, type_: &TypeRef, value: &Value) -> Result<()> {
+		match value {
+			Value::Void => builder.set_void(()),
+			Value::Bool(value) => builder.set_bool(*value),
+			Value::Int8(value) => builder.set_int8(*value),
+			Value::Int16(value) => builder.set_int16(*value),
+			Value::Int32(value) => builder.set_int32(*value),
+			Value::Int64(value) => builder.set_int64(*value),
+			Value::Uint8(value) => builder.set_uint8(*value),
+			Value::Uint16(value) => builder.set_uint16(*value),
+			Value::Uint32(value) => builder.set_uint32(*value),
+			Value::Uint64(value) => builder.set_uint64(*value),
+			Value::Float32(value) => builder.set_float32(*value),
+			Value::Float64(value) => builder.set_float64(*value),
+			Value::Enum(value) => builder.set_enum(*value),
+			Value::Text(text) => builder.set_text(text),
+			Value::Data(data) => builder.set_data(data),
+			Value::List(_) => {
+				let pointer = builder.init_list().init_as::<RawPointer>().0;
+				self.write_pointer(pointer, type_, value)?;
+			}
+			Value::Struct(..) => {
+				let pointer = builder.init_struct().init_as::<RawPointer>().0;
+				self.write_pointer(pointer, type_, value)?;
+			}
+			// an unset pointer still says which kind of value it is
+			Value::Null => match type_ {
+				TypeRef::Text => {
+					builder.init_text(0);
+				}
+				TypeRef::Data => {
+					builder.init_data(0);
+				}
+				TypeRef::List(_) => {
+					builder.init_list();
+				}
+				TypeRef::Struct(..) => {
+					builder.init_struct();
+				}
+				TypeRef::Interface(..) => builder.set_interface(()),
+				_ => {
+					builder.init_any_pointer();
+				}
+			},
+		}
+
+		Ok(())
+	}
+
+	fn write_pointer(&self, builder: PointerBuilder, type_: &TypeRef, value: &Value) -> Result<()> {
+		match (type_, value) {
+			(_, Value::Text(text)) => builder.set_text(text),
+			(_, Value::Data(data)) => builder.set_data(data),
+			(TypeRef::List(element), Value::List(items)) => self.write_list(builder, element, items)?,
+			(_, Value::Struct(id, brand, fields)) => {
+				let def = self.struct_def(*id)?;
+				let size = StructSize {
+					data: def.data_words,
+					pointers: def.pointers,
+				};
+				self.write_struct(builder.init_struct(size), *id, brand, fields)?;
+			}
+			_ => {}
+		}
+
+		Ok(())
+	}
+
+	fn write_list(&self, builder: PointerBuilder, element: &TypeRef, items: &[Value]) -> Result<()> {
+		let len = items.len() as u32;
+
+		match (element.size(), element) {
+			(Size::Void, _) => {
+				builder.init_list(ElementSize::Void, len);
+			}
+			(Size::Data(lg_size), _) => {
+				let size = match lg_size {
+					0 => ElementSize::Bit,
+					3 => ElementSize::Byte,
+					4 => ElementSize::TwoBytes,
+					5 => ElementSize::FourBytes,
+					_ => ElementSize::EightBytes,
+				};
+				let list = builder.init_list(size, len);
+				for (i, item) in items.iter().enumerate() {
+					set_element(&list, i as u32, item);
+				}
+			}
+			(Size::Pointer, TypeRef::Struct(id, _)) => {
+				let def = self.struct_def(*id)?;
+				let size = StructSize {
+					data: def.data_words,
+					pointers: def.pointers,
+				};
+				let list = builder.init_struct_list(len, size);
+				for (i, item) in items.iter().enumerate() {
+					if let Value::Struct(id, brand, fields) = item {
+						self.write_struct(list.get_struct_element(i as u32), *id, brand, fields)?;
+					}
+				}
+			}
+			(Size::Pointer, _) => {
+				let list = builder.init_list(ElementSize::Pointer, len);
+				for (i, item) in items.iter().enumerate() {
+					self.write_pointer(list.get_pointer_element(i as u32), element, item)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn write_struct(
+		&self,
+		builder: StructBuilder,
+		id: u64,
+		brand: &Brand,
+		fields: &[(usize, Value)],
+	) -> Result<()> {
+		let def = self.struct_def(id)?;
+
+		for (index, value) in fields {
+			let field = &def.fields[*index];
+			if let Some(discriminant) = field.discriminant {
+				builder.set_data_field::<u16>(def.discriminant_offset as usize, discriminant);
+			}
+
+			match &field.kind {
+				FieldKind::Group(group) => {
+					if let Value::Struct(_, _, fields) = value {
+						self.write_struct(builder, *group, brand, fields)?;
+					}
+				}
+				FieldKind::Slot {
+					type_,
+					offset,
+					default,
+				} => {
+					let offset = *offset as usize;
+					let type_ = &type_.substitute(brand);
+					match type_.size() {
+						Size::Void => {}
+						Size::Pointer => {
+							self.write_pointer(builder.get_pointer_field(offset), type_, value)?
+						}
+						Size::Data(_) => {
+							// data fields are stored XORed with their default
+							let default = match default {
+								Some(default) => self.eval(default, type_, def.lookup)?,
+								None => Value::zero(type_),
+							};
+							set_field(builder, offset, value, &default);
+						}
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Converts an integer literal to `type_`, or `None` if it is out of range.
+fn int(type_: &TypeRef, value: i128) -> Option<Value> {
+	let value = match type_ {
+		TypeRef::Int8 => Value::Int8(value.try_into().ok()?),
+		TypeRef::Int16 => Value::Int16(value.try_into().ok()?),
+		TypeRef::Int32 => Value::Int32(value.try_into().ok()?),
+		TypeRef::Int64 => Value::Int64(value.try_into().ok()?),
+		TypeRef::Uint8 => Value::Uint8(value.try_into().ok()?),
+		TypeRef::Uint16 => Value::Uint16(value.try_into().ok()?),
+		TypeRef::Uint32 => Value::Uint32(value.try_into().ok()?),
+		TypeRef::Uint64 => Value::Uint64(value.try_into().ok()?),
+		TypeRef::Float32 => Value::Float32(value as f32),
+		TypeRef::Float64 => Value::Float64(value as f64),
+		_ => return None,
+	};
+
+	Some(value)
+}
+
+fn set_element(list: &ListBuilder, index: u32, value: &Value) {
+	match *value {
+		Value::Bool(value) => PrimitiveElement::set(list, index, value),
+		Value::Int8(value) => PrimitiveElement::set(list, index, value),
+		Value::Int16(value) => PrimitiveElement::set(list, index, value),
+		Value::Int32(value) => PrimitiveElement::set(list, index, value),
+		Value::Int64(value) => PrimitiveElement::set(list, index, value),
+		Value::Uint8(value) => PrimitiveElement::set(list, index, value),
+		Value::Uint16(value) | Value::Enum(value) => PrimitiveElement::set(list, index, value),
+		Value::Uint32(value) => PrimitiveElement::set(list, index, value),
+		Value::Uint64(value) => PrimitiveElement::set(list, index, value),
+		Value::Float32(value) => PrimitiveElement::set(list, index, value),
+		Value::Float64(value) => PrimitiveElement::set(list, index, value),
+		_ => {}
+	}
+}
+
+fn set_field(builder: StructBuilder, offset: usize, value: &Value, default: &Value) {
+	match (value, default) {
+		(Value::Bool(value), Value::Bool(mask)) => builder.set_bool_field_mask(offset, *value, *mask),
+		(Value::Int8(value), Value::Int8(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Int16(value), Value::Int16(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Int32(value), Value::Int32(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Int64(value), Value::Int64(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Uint8(value), Value::Uint8(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Uint16(value), Value::Uint16(mask)) | (Value::Enum(value), Value::Enum(mask)) => {
+			builder.set_data_field_mask(offset, *value, *mask)
+		}
+		(Value::Uint32(value), Value::Uint32(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Uint64(value), Value::Uint64(mask)) => builder.set_data_field_mask(offset, *value, *mask),
+		(Value::Float32(value), Value::Float32(mask)) => {
+			builder.set_data_field_mask(offset, *value, mask.to_bits())
+		}
+		(Value::Float64(value), Value::Float64(mask)) => {
+			builder.set_data_field_mask(offset, *value, mask.to_bits())
+		}
+		_ => {}
+	}
+}
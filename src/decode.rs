@@ -0,0 +1,222 @@
+use crate::get_node;
+use anyhow::{bail, Result};
+use capnp::private::layout::{ElementSize, PointerReader, PrimitiveElement, StructReader};
+use capnp::traits::FromPointerReader;
+use capnp::{primitive_list, Word};
+use capnpc::codegen::GeneratorContext;
+use capnpc::schema_capnp::node::WhichReader;
+use capnpc::schema_capnp::{field, type_, value};
+use serde_json::{Map, Number, Value};
+
+/// The untyped pointer behind an `AnyPointer`, so it can be walked against a schema type.
+struct RawPointer<'a>(PointerReader<'a>);
+
+impl<'a> FromPointerReader<'a> for RawPointer<'a> {
+	fn get_from_pointer(reader: &PointerReader<'a>, _default: Option<&'a [Word]>) -> capnp::Result<Self> {
+		Ok(RawPointer(*reader))
+	}
+}
+
+/// Decodes a schema value into JSON, using `type_` to interpret enums, lists and structs.
+pub fn value(value: value::Reader, type_: type_::Reader, gen: &GeneratorContext) -> Result<Value> {
+	let decoded = match value.which()? {
+		value::Void(()) => Value::Null,
+		value::Bool(v) => v.into(),
+		value::Int8(v) => v.into(),
+		value::Int16(v) => v.into(),
+		value::Int32(v) => v.into(),
+		value::Int64(v) => v.into(),
+		value::Uint8(v) => v.into(),
+		value::Uint16(v) => v.into(),
+		value::Uint32(v) => v.into(),
+		value::Uint64(v) => v.into(),
+		value::Float32(v) => float(v.into()),
+		value::Float64(v) => float(v),
+		value::Text(v) => v?.into(),
+		value::Data(v) => hex(v?).into(),
+		value::Enum(v) => enumerant(type_, v, gen)?,
+		value::List(v) | value::Struct(v) | value::AnyPointer(v) => {
+			pointer(v.get_as::<RawPointer>()?.0, type_, gen)?
+		}
+		value::Interface(()) => Value::Null,
+	};
+
+	Ok(decoded)
+}
+
+fn float(value: f64) -> Value {
+	// JSON has no representation for NaN or the infinities
+	Number::from_f64(value).map_or_else(|| value.to_string().into(), Value::Number)
+}
+
+fn hex(data: &[u8]) -> String {
+	data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn enumerant(type_: type_::Reader, index: u16, gen: &GeneratorContext) -> Result<Value> {
+	if let type_::Enum(reader) = type_.which()? {
+		if let WhichReader::Enum(reader) = get_node(gen, reader.get_type_id())?.which()? {
+			let enumerants = reader.get_enumerants()?;
+
+			if u32::from(index) < enumerants.len() {
+				return Ok(enumerants.get(index.into()).get_name()?.into());
+			}
+		}
+	}
+
+	// unknown enumerants are kept as their raw number
+	Ok(index.into())
+}
+
+fn pointer(reader: PointerReader, type_: type_::Reader, gen: &GeneratorContext) -> Result<Value> {
+	if reader.is_null() {
+		return Ok(Value::Null);
+	}
+
+	let decoded = match type_.which()? {
+		type_::Text(()) => reader.get_text(None)?.into(),
+		type_::Data(()) => hex(reader.get_data(None)?).into(),
+		type_::List(list) => self::list(reader, list.get_element_type()?, gen)?,
+		type_::Struct(target) => structure(reader.get_struct(None)?, target.get_type_id(), gen)?,
+		// capabilities and unconstrained pointers have no schema to decode against
+		_ => Value::Null,
+	};
+
+	Ok(decoded)
+}
+
+fn primitives<'a, T>(reader: &PointerReader<'a>, convert: impl Fn(T) -> Value) -> Result<Vec<Value>>
+where
+	T: PrimitiveElement,
+{
+	let list = primitive_list::Reader::<T>::get_from_pointer(reader, None)?;
+
+	Ok(list.iter().map(convert).collect())
+}
+
+fn list(reader: PointerReader, element: type_::Reader, gen: &GeneratorContext) -> Result<Value> {
+	let items = match element.which()? {
+		type_::Void(()) => primitives::<()>(&reader, |_| Value::Null)?,
+		type_::Bool(()) => primitives::<bool>(&reader, Value::from)?,
+		type_::Int8(()) => primitives::<i8>(&reader, Value::from)?,
+		type_::Int16(()) => primitives::<i16>(&reader, Value::from)?,
+		type_::Int32(()) => primitives::<i32>(&reader, Value::from)?,
+		type_::Int64(()) => primitives::<i64>(&reader, Value::from)?,
+		type_::Uint8(()) => primitives::<u8>(&reader, Value::from)?,
+		type_::Uint16(()) => primitives::<u16>(&reader, Value::from)?,
+		type_::Uint32(()) => primitives::<u32>(&reader, Value::from)?,
+		type_::Uint64(()) => primitives::<u64>(&reader, Value::from)?,
+		type_::Float32(()) => primitives::<f32>(&reader, |v| float(v.into()))?,
+		type_::Float64(()) => primitives::<f64>(&reader, float)?,
+		type_::Enum(_) => {
+			let list = primitive_list::Reader::<u16>::get_from_pointer(&reader, None)?;
+
+			list.iter()
+				.map(|v| enumerant(element, v, gen))
+				.collect::<Result<_>>()?
+		}
+		type_::Struct(target) => {
+			let list = reader.get_list(ElementSize::InlineComposite, None)?;
+
+			(0..list.len())
+				.map(|i| structure(list.get_struct_element(i), target.get_type_id(), gen))
+				.collect::<Result<_>>()?
+		}
+		_ => {
+			let list = reader.get_list(ElementSize::Pointer, None)?;
+
+			(0..list.len())
+				.map(|i| pointer(list.get_pointer_element(i), element, gen))
+				.collect::<Result<_>>()?
+		}
+	};
+
+	Ok(Value::Array(items))
+}
+
+fn structure(reader: StructReader, id: u64, gen: &GeneratorContext) -> Result<Value> {
+	let node = match get_node(gen, id)?.which()? {
+		WhichReader::Struct(node) => node,
+		_ => bail!("node {id:#018x} is not a struct"),
+	};
+
+	// only the active member of an unnamed union is present
+	let discriminant = (node.get_discriminant_count() > 0)
+		.then(|| reader.get_data_field::<u16>(node.get_discriminant_offset() as usize));
+
+	let mut object = Map::new();
+	for field in node.get_fields()?.iter() {
+		let discriminant_value = field.get_discriminant_value();
+		if discriminant_value != field::NO_DISCRIMINANT && Some(discriminant_value) != discriminant {
+			continue;
+		}
+
+		let value = match field.which()? {
+			field::Slot(slot) => self::slot(reader, slot, gen)?,
+			field::Group(group) => structure(reader, group.get_type_id(), gen)?,
+		};
+
+		object.insert(field.get_name()?.to_string(), value);
+	}
+
+	Ok(Value::Object(object))
+}
+
+fn slot(reader: StructReader, slot: field::slot::Reader, gen: &GeneratorContext) -> Result<Value> {
+	let offset = slot.get_offset() as usize;
+	let type_ = slot.get_type()?;
+	let default = slot.get_default_value()?;
+
+	// primitive fields are stored XORed with their default value
+	let decoded = match (type_.which()?, default.which()?) {
+		(type_::Void(()), _) => Value::Null,
+		(type_::Bool(()), value::Bool(mask)) => reader.get_bool_field_mask(offset, mask).into(),
+		(type_::Int8(()), value::Int8(mask)) => reader.get_data_field_mask::<i8>(offset, mask).into(),
+		(type_::Int16(()), value::Int16(mask)) => reader.get_data_field_mask::<i16>(offset, mask).into(),
+		(type_::Int32(()), value::Int32(mask)) => reader.get_data_field_mask::<i32>(offset, mask).into(),
+		(type_::Int64(()), value::Int64(mask)) => reader.get_data_field_mask::<i64>(offset, mask).into(),
+		(type_::Uint8(()), value::Uint8(mask)) => reader.get_data_field_mask::<u8>(offset, mask).into(),
+		(type_::Uint16(()), value::Uint16(mask)) => reader.get_data_field_mask::<u16>(offset, mask).into(),
+		(type_::Uint32(()), value::Uint32(mask)) => reader.get_data_field_mask::<u32>(offset, mask).into(),
+		(type_::Uint64(()), value::Uint64(mask)) => reader.get_data_field_mask::<u64>(offset, mask).into(),
+		(type_::Float32(()), value::Float32(mask)) => {
+			float(reader.get_data_field_mask::<f32>(offset, mask.to_bits()).into())
+		}
+		(type_::Float64(()), value::Float64(mask)) => {
+			float(reader.get_data_field_mask::<f64>(offset, mask.to_bits()))
+		}
+		(type_::Enum(_), value::Enum(mask)) => {
+			enumerant(type_, reader.get_data_field_mask::<u16>(offset, mask), gen)?
+		}
+		(
+			type_::Text(())
+			| type_::Data(())
+			| type_::List(_)
+			| type_::Struct(_)
+			| type_::Interface(_)
+			| type_::AnyPointer(_),
+			_,
+		) => {
+			let target = reader.get_pointer_field(offset);
+
+			// unset pointer fields fall back to their default value, unless it holds structs,
+			// whose own unset fields could fall back to the same default forever
+			if target.is_null() && !holds_structs(type_)? {
+				self::value(default, type_, gen)?
+			} else {
+				pointer(target, type_, gen)?
+			}
+		}
+		_ => bail!("default value does not match the type of its field"),
+	};
+
+	Ok(decoded)
+}
+
+fn holds_structs(type_: type_::Reader) -> Result<bool> {
+	Ok(match type_.which()? {
+		type_::Struct(_) => true,
+		type_::List(list) => holds_structs(list.get_element_type()?)?,
+		_ => false,
+	})
+}
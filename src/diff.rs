@@ -0,0 +1,838 @@
+use crate::{
+	hex_id, ordered_map, Annotation, Annotations, BrandScope, Const, Declaration, Enum, Field, File,
+	Generics, Interface, Method, Results, Struct, Type,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::{self, Write};
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+	/// Output JSON of the older schemas
+	old: String,
+
+	/// Output JSON of the newer schemas
+	new: String,
+
+	/// Format of the emitted diff
+	#[arg(short, long, value_enum, default_value_t = Format::Text)]
+	format: Format,
+
+	/// Filepath for the diff, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	/// One line per change, with the changed properties indented below it
+	Text,
+	/// The list of changes
+	Json,
+}
+
+impl DiffArgs {
+	pub fn run(self) -> Result<()> {
+		let old = Results::load(&self.old)?;
+		let new = Results::load(&self.new)?;
+		let rendered = Diff::new(&old, &new).render(self.format)?;
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => print!("{rendered}"),
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+	Added,
+	Removed,
+	Changed,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Item {
+	File,
+	Struct,
+	Field,
+	Enum,
+	Enumerant,
+	Interface,
+	Method,
+	Const,
+	Annotation,
+}
+
+impl fmt::Display for Item {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+		write!(f, "{}", name.as_str().unwrap_or_default())
+	}
+}
+
+/// One property that differs between the two sides of a change.
+#[derive(Serialize, Clone, Debug)]
+pub struct Detail {
+	/// The property that changed, or `$name` for an annotation.
+	pub property: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub old: Option<serde_json::Value>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub new: Option<serde_json::Value>,
+}
+
+impl fmt::Display for Detail {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// strings read better without their JSON quotes
+		let show = |value: &serde_json::Value| match value {
+			serde_json::Value::String(text) => text.clone(),
+			value => value.to_string(),
+		};
+
+		match (&self.old, &self.new) {
+			// doc comments are rarely short enough to show inline
+			_ if self.property == "doc" => write!(f, "doc changed"),
+			(Some(old), Some(new)) => write!(f, "{}: {} -> {}", self.property, show(old), show(new)),
+			(None, Some(new)) => write!(f, "{} added: {}", self.property, show(new)),
+			(Some(old), None) => write!(f, "{} removed: {}", self.property, show(old)),
+			(None, None) => write!(f, "{} changed", self.property),
+		}
+	}
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Change {
+	pub change: ChangeKind,
+	pub item: Item,
+	/// Display name of the node, followed by the member path for fields, enumerants and methods.
+	pub name: String,
+	/// The node that declares the item.
+	#[serde(with = "hex_id")]
+	pub id: u64,
+	/// Where a member sits below its node, by ordinal or by group ID, so it survives renames.
+	/// Empty for the node itself.
+	#[serde(skip_serializing_if = "String::is_empty")]
+	pub member: String,
+	pub file: String,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub details: Vec<Detail>,
+	/// Annotations on the newer side, or the older side for removals.
+	#[serde(serialize_with = "ordered_map", skip_serializing_if = "Annotations::is_empty")]
+	pub annotations: Annotations,
+}
+
+/// Every added, removed or changed item between two `Results` documents. Nodes are matched by
+/// ID and members by ordinal, so a rename is a change of `name`. A node is listed before the
+/// changes to its members, which for an added or removed node are all of them.
+#[derive(Serialize, Debug)]
+pub struct Diff {
+	pub changes: Vec<Change>,
+}
+
+enum Matched<'a, T> {
+	Removed(&'a T),
+	Added(&'a T),
+	Both(&'a T, &'a T),
+}
+
+/// Pairs up items by `key`, in the older document's order followed by anything new.
+fn matched<'a, T, K>(old: &'a [T], new: &'a [T], key: impl Fn(&T) -> K) -> Vec<Matched<'a, T>>
+where
+	K: PartialEq,
+{
+	let mut pairs = vec![];
+
+	for item in old {
+		match new.iter().find(|other| key(other) == key(item)) {
+			Some(other) => pairs.push(Matched::Both(item, other)),
+			None => pairs.push(Matched::Removed(item)),
+		}
+	}
+
+	for item in new {
+		if !old.iter().any(|other| key(other) == key(item)) {
+			pairs.push(Matched::Added(item));
+		}
+	}
+
+	pairs
+}
+
+/// Records `property` when the two sides serialize differently.
+fn compare<T>(details: &mut Vec<Detail>, property: &str, old: &T, new: &T)
+where
+	T: Serialize,
+{
+	let value = |value: &T| match serde_json::to_value(value) {
+		Ok(serde_json::Value::Null) | Err(_) => None,
+		Ok(value) => Some(value),
+	};
+
+	let (old, new) = (value(old), value(new));
+	if old != new {
+		details.push(Detail {
+			property: property.to_string(),
+			old,
+			new,
+		});
+	}
+}
+
+fn compare_annotations(details: &mut Vec<Detail>, old: &Annotations, new: &Annotations) {
+	let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+	for name in names {
+		compare(details, &format!("${name}"), &old.get(name), &new.get(name));
+	}
+}
+
+/// What a field is matched by across documents.
+#[derive(PartialEq)]
+enum FieldKey {
+	Ordinal(u16),
+	/// Groups have no ordinal, but their node's ID is just as stable.
+	Group(u64),
+	/// Adding a field above another shifts its code order, so this is the last resort.
+	CodeOrder(Option<u16>),
+}
+
+fn field_key(field: &Field) -> FieldKey {
+	match (field.ordinal, &field.group) {
+		(Some(ordinal), _) => FieldKey::Ordinal(ordinal),
+		(None, Some(group)) => FieldKey::Group(group.id),
+		(None, None) => FieldKey::CodeOrder(field.code_order),
+	}
+}
+
+impl fmt::Display for FieldKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			FieldKey::Ordinal(ordinal) => write!(f, "@{ordinal}"),
+			FieldKey::Group(id) => write!(f, "{id:#018x}"),
+			FieldKey::CodeOrder(Some(code_order)) => write!(f, "#{code_order}"),
+			FieldKey::CodeOrder(None) => write!(f, "#"),
+		}
+	}
+}
+
+/// A member's place below its node, both for display and as `Change::member`.
+struct Location {
+	name: String,
+	member: String,
+}
+
+impl Location {
+	fn node(declaration: &Declaration) -> Self {
+		Location {
+			name: declaration.name.clone(),
+			member: String::new(),
+		}
+	}
+
+	fn child(&self, name: &str, key: impl fmt::Display) -> Self {
+		let member = match self.member.as_str() {
+			"" => key.to_string(),
+			parent => format!("{parent}.{key}"),
+		};
+
+		Location {
+			name: format!("{}.{name}", self.name),
+			member,
+		}
+	}
+}
+
+fn compare_declarations(details: &mut Vec<Detail>, old: &Declaration, new: &Declaration) {
+	compare(details, "name", &old.name, &new.name);
+	compare(details, "doc", &old.doc, &new.doc);
+	compare_annotations(details, &old.annotations, &new.annotations);
+}
+
+fn compare_generics(details: &mut Vec<Detail>, old: &Generics, new: &Generics) {
+	compare(details, "parameters", &old.parameters, &new.parameters);
+	compare(details, "is_generic", &old.is_generic, &new.is_generic);
+}
+
+/// Whether two types are the same on the wire and in the generated code. Nodes are compared by
+/// ID, since renaming one doesn't change the types that use it.
+fn same_type(old: &Type, new: &Type) -> bool {
+	match (old, new) {
+		(Type::List { element: old }, Type::List { element: new }) => same_type(old, new),
+		(
+			Type::Enum {
+				id: old_id,
+				brand: old_brand,
+				..
+			},
+			Type::Enum {
+				id: new_id,
+				brand: new_brand,
+				..
+			},
+		)
+		| (
+			Type::Struct {
+				id: old_id,
+				brand: old_brand,
+				..
+			},
+			Type::Struct {
+				id: new_id,
+				brand: new_brand,
+				..
+			},
+		)
+		| (
+			Type::Interface {
+				id: old_id,
+				brand: old_brand,
+				..
+			},
+			Type::Interface {
+				id: new_id,
+				brand: new_brand,
+				..
+			},
+		) => old_id == new_id && same_brand(old_brand, new_brand),
+		(
+			Type::Parameter {
+				scope_id: old_scope,
+				index: old_index,
+				..
+			},
+			Type::Parameter {
+				scope_id: new_scope,
+				index: new_index,
+				..
+			},
+		) => old_scope == new_scope && old_index == new_index,
+		(old, new) => old == new,
+	}
+}
+
+fn same_brand(old: &[BrandScope], new: &[BrandScope]) -> bool {
+	old.len() == new.len()
+		&& old.iter().zip(new).all(|(old, new)| {
+			old.scope_id == new.scope_id
+				&& old.bindings.len() == new.bindings.len()
+				&& old
+					.bindings
+					.iter()
+					.zip(&new.bindings)
+					.all(|(old, new)| match (&old.type_, &new.type_) {
+						(Some(old), Some(new)) => same_type(old, new),
+						(old, new) => old.is_none() && new.is_none(),
+					})
+		})
+}
+
+/// Types are compared by kind and ID, but shown the way a schema writes them.
+fn compare_types(details: &mut Vec<Detail>, property: &str, old: Option<&Type>, new: Option<&Type>) {
+	let same = match (old, new) {
+		(Some(old), Some(new)) => same_type(old, new),
+		(old, new) => old.is_none() && new.is_none(),
+	};
+
+	if !same {
+		details.push(Detail {
+			property: property.to_string(),
+			old: old.as_ref().map(|type_| type_.to_string().into()),
+			new: new.as_ref().map(|type_| type_.to_string().into()),
+		});
+	}
+}
+
+impl Diff {
+	pub fn new(old: &Results, new: &Results) -> Self {
+		let mut diff = Diff { changes: vec![] };
+
+		diff.files(&old.files, &new.files);
+		diff.structs(&old.structs, &new.structs);
+		diff.enums(&old.enums, &new.enums);
+		diff.interfaces(&old.interfaces, &new.interfaces);
+		diff.consts(&old.consts, &new.consts);
+		diff.annotations(&old.annotations, &new.annotations);
+
+		diff
+	}
+
+	pub fn render(&self, format: Format) -> Result<String> {
+		match format {
+			Format::Text => Ok(self.to_text()),
+			Format::Json => Ok(serde_json::to_string_pretty(self)?),
+		}
+	}
+
+	pub fn to_text(&self) -> String {
+		let mut text = String::new();
+
+		for change in &self.changes {
+			let marker = match change.change {
+				ChangeKind::Added => '+',
+				ChangeKind::Removed => '-',
+				ChangeKind::Changed => '~',
+			};
+
+			let _ = writeln!(text, "{marker} {} {}", change.item, change.name);
+			for detail in &change.details {
+				let _ = writeln!(text, "    {detail}");
+			}
+		}
+
+		text
+	}
+
+	/// Adds a change to a whole node.
+	fn node(&mut self, change: ChangeKind, item: Item, declaration: &Declaration, details: Vec<Detail>) {
+		self.changes.push(Change {
+			change,
+			item,
+			name: declaration.name.clone(),
+			id: declaration.id,
+			member: String::new(),
+			file: declaration.file.clone(),
+			details,
+			annotations: declaration.annotations.clone(),
+		});
+	}
+
+	/// Adds a change to a member of `parent`, found at `location` below it.
+	fn member(
+		&mut self,
+		change: ChangeKind,
+		item: Item,
+		parent: &Declaration,
+		location: &Location,
+		details: Vec<Detail>,
+		annotations: &Annotations,
+	) {
+		self.changes.push(Change {
+			change,
+			item,
+			name: location.name.clone(),
+			id: parent.id,
+			member: location.member.clone(),
+			file: parent.file.clone(),
+			details,
+			annotations: annotations.clone(),
+		});
+	}
+
+	/// Moves a node's change in front of the member changes recorded since `at`, if it has any details.
+	fn changed_node(&mut self, at: usize, item: Item, declaration: &Declaration, details: Vec<Detail>) {
+		if details.is_empty() {
+			return;
+		}
+
+		self.node(ChangeKind::Changed, item, declaration, details);
+		let change = self.changes.pop().unwrap();
+		self.changes.insert(at, change);
+	}
+
+	fn files(&mut self, old: &[File], new: &[File]) {
+		let change = |change, file: &File, details| Change {
+			change,
+			item: Item::File,
+			name: file.name.clone(),
+			id: file.id,
+			member: String::new(),
+			file: file.name.clone(),
+			details,
+			annotations: file.annotations.clone(),
+		};
+
+		for pair in matched(old, new, |file| file.id) {
+			match pair {
+				Matched::Removed(file) => self.changes.push(change(ChangeKind::Removed, file, vec![])),
+				Matched::Added(file) => self.changes.push(change(ChangeKind::Added, file, vec![])),
+				Matched::Both(old, new) => {
+					let imports = |file: &File| -> Vec<String> {
+						file.imports.iter().map(|import| import.name.clone()).collect()
+					};
+
+					let mut details = vec![];
+					compare(&mut details, "name", &old.name, &new.name);
+					compare(&mut details, "requested", &old.requested, &new.requested);
+					compare(&mut details, "imports", &imports(old), &imports(new));
+					compare_annotations(&mut details, &old.annotations, &new.annotations);
+
+					if !details.is_empty() {
+						self.changes.push(change(ChangeKind::Changed, new, details));
+					}
+				}
+			}
+		}
+	}
+
+	fn structs(&mut self, old: &[Struct], new: &[Struct]) {
+		for pair in matched(old, new, |node| node.declaration.id) {
+			match pair {
+				Matched::Removed(node) => {
+					self.node(ChangeKind::Removed, Item::Struct, &node.declaration, vec![]);
+					self.fields(&node.declaration, &Location::node(&node.declaration), &node.fields, &[]);
+				}
+				Matched::Added(node) => {
+					self.node(ChangeKind::Added, Item::Struct, &node.declaration, vec![]);
+					self.fields(&node.declaration, &Location::node(&node.declaration), &[], &node.fields);
+				}
+				Matched::Both(old, new) => {
+					let at = self.changes.len();
+					let mut details = vec![];
+					compare_declarations(&mut details, &old.declaration, &new.declaration);
+					compare_generics(&mut details, &old.generics, &new.generics);
+					compare(&mut details, "union", &old.union, &new.union);
+
+					let location = Location::node(&new.declaration);
+					self.fields(&new.declaration, &location, &old.fields, &new.fields);
+					self.changed_node(at, Item::Struct, &new.declaration, details);
+				}
+			}
+		}
+	}
+
+	/// Compares the fields of a struct, group or param list, descending into groups.
+	fn fields(&mut self, parent: &Declaration, scope: &Location, old: &[Field], new: &[Field]) {
+		for pair in matched(old, new, field_key) {
+			match pair {
+				Matched::Removed(field) => {
+					let location = scope.child(&field.name, field_key(field));
+					self.member(
+						ChangeKind::Removed,
+						Item::Field,
+						parent,
+						&location,
+						vec![],
+						&field.annotations,
+					);
+					if let Some(group) = &field.group {
+						self.fields(parent, &location, &group.fields, &[]);
+					}
+				}
+				Matched::Added(field) => {
+					let location = scope.child(&field.name, field_key(field));
+					self.member(
+						ChangeKind::Added,
+						Item::Field,
+						parent,
+						&location,
+						vec![],
+						&field.annotations,
+					);
+					if let Some(group) = &field.group {
+						self.fields(parent, &location, &[], &group.fields);
+					}
+				}
+				Matched::Both(old, new) => {
+					let location = scope.child(&new.name, field_key(new));
+					let at = self.changes.len();
+
+					let mut details = vec![];
+					compare(&mut details, "name", &old.name, &new.name);
+					compare_types(&mut details, "type", old.type_.as_ref(), new.type_.as_ref());
+					compare(&mut details, "offset", &old.offset, &new.offset);
+					compare(
+						&mut details,
+						"had_explicit_default",
+						&old.had_explicit_default,
+						&new.had_explicit_default,
+					);
+					compare(&mut details, "default", &old.default, &new.default);
+					compare(
+						&mut details,
+						"discriminant_value",
+						&old.discriminant_value,
+						&new.discriminant_value,
+					);
+
+					match (&old.group, &new.group) {
+						(Some(old_group), Some(new_group)) => {
+							compare(
+								&mut details,
+								"group.id",
+								&format!("{:#018x}", old_group.id),
+								&format!("{:#018x}", new_group.id),
+							);
+							compare(&mut details, "group.union", &old_group.union, &new_group.union);
+							self.fields(parent, &location, &old_group.fields, &new_group.fields);
+						}
+						(old_group, new_group) => {
+							compare(&mut details, "group", &old_group.is_some(), &new_group.is_some());
+						}
+					}
+
+					compare(&mut details, "doc", &old.doc, &new.doc);
+					compare_annotations(&mut details, &old.annotations, &new.annotations);
+
+					if !details.is_empty() {
+						self.member(
+							ChangeKind::Changed,
+							Item::Field,
+							parent,
+							&location,
+							details,
+							&new.annotations,
+						);
+						let change = self.changes.pop().unwrap();
+						self.changes.insert(at, change);
+					}
+				}
+			}
+		}
+	}
+
+	fn enums(&mut self, old: &[Enum], new: &[Enum]) {
+		for pair in matched(old, new, |node| node.declaration.id) {
+			match pair {
+				Matched::Removed(node) => {
+					self.node(ChangeKind::Removed, Item::Enum, &node.declaration, vec![]);
+					self.enumerants(&node.declaration, &node.enumerants, &[]);
+				}
+				Matched::Added(node) => {
+					self.node(ChangeKind::Added, Item::Enum, &node.declaration, vec![]);
+					self.enumerants(&node.declaration, &[], &node.enumerants);
+				}
+				Matched::Both(old, new) => {
+					let at = self.changes.len();
+					let mut details = vec![];
+					compare_declarations(&mut details, &old.declaration, &new.declaration);
+					compare_generics(&mut details, &old.generics, &new.generics);
+
+					self.enumerants(&new.declaration, &old.enumerants, &new.enumerants);
+					self.changed_node(at, Item::Enum, &new.declaration, details);
+				}
+			}
+		}
+	}
+
+	fn enumerants(&mut self, parent: &Declaration, old: &[Field], new: &[Field]) {
+		// an enumerant's ordinal is its position in the list
+		let old: Vec<(usize, &Field)> = old.iter().enumerate().collect();
+		let new: Vec<(usize, &Field)> = new.iter().enumerate().collect();
+
+		for pair in matched(&old, &new, |(ordinal, _)| *ordinal) {
+			match pair {
+				Matched::Removed((ordinal, enumerant)) => {
+					let location = Location::node(parent).child(&enumerant.name, format!("@{ordinal}"));
+					self.member(
+						ChangeKind::Removed,
+						Item::Enumerant,
+						parent,
+						&location,
+						vec![],
+						&enumerant.annotations,
+					);
+				}
+				Matched::Added((ordinal, enumerant)) => {
+					let location = Location::node(parent).child(&enumerant.name, format!("@{ordinal}"));
+					self.member(
+						ChangeKind::Added,
+						Item::Enumerant,
+						parent,
+						&location,
+						vec![],
+						&enumerant.annotations,
+					);
+				}
+				Matched::Both((_, old_enumerant), (ordinal, new_enumerant)) => {
+					let mut details = vec![];
+					compare(&mut details, "name", &old_enumerant.name, &new_enumerant.name);
+					compare(&mut details, "doc", &old_enumerant.doc, &new_enumerant.doc);
+					compare_annotations(
+						&mut details,
+						&old_enumerant.annotations,
+						&new_enumerant.annotations,
+					);
+
+					if !details.is_empty() {
+						let location =
+							Location::node(parent).child(&new_enumerant.name, format!("@{ordinal}"));
+						self.member(
+							ChangeKind::Changed,
+							Item::Enumerant,
+							parent,
+							&location,
+							details,
+							&new_enumerant.annotations,
+						);
+					}
+				}
+			}
+		}
+	}
+
+	fn interfaces(&mut self, old: &[Interface], new: &[Interface]) {
+		for pair in matched(old, new, |node| node.declaration.id) {
+			match pair {
+				Matched::Removed(node) => {
+					self.node(ChangeKind::Removed, Item::Interface, &node.declaration, vec![]);
+					self.methods(&node.declaration, &node.methods, &[]);
+				}
+				Matched::Added(node) => {
+					self.node(ChangeKind::Added, Item::Interface, &node.declaration, vec![]);
+					self.methods(&node.declaration, &[], &node.methods);
+				}
+				Matched::Both(old, new) => {
+					let superclasses = |node: &Interface| -> Vec<String> {
+						node.superclasses
+							.iter()
+							.map(|superclass| superclass.name.clone())
+							.collect()
+					};
+
+					let at = self.changes.len();
+					let mut details = vec![];
+					compare_declarations(&mut details, &old.declaration, &new.declaration);
+					compare_generics(&mut details, &old.generics, &new.generics);
+					compare(
+						&mut details,
+						"superclasses",
+						&superclasses(old),
+						&superclasses(new),
+					);
+
+					self.methods(&new.declaration, &old.methods, &new.methods);
+					self.changed_node(at, Item::Interface, &new.declaration, details);
+				}
+			}
+		}
+	}
+
+	fn methods(&mut self, parent: &Declaration, old: &[Method], new: &[Method]) {
+		for pair in matched(old, new, |method| method.ordinal) {
+			match pair {
+				Matched::Removed(method) => {
+					let location = Location::node(parent).child(&method.name, format!("@{}", method.ordinal));
+					self.member(
+						ChangeKind::Removed,
+						Item::Method,
+						parent,
+						&location,
+						vec![],
+						&method.annotations,
+					);
+				}
+				Matched::Added(method) => {
+					let location = Location::node(parent).child(&method.name, format!("@{}", method.ordinal));
+					self.member(
+						ChangeKind::Added,
+						Item::Method,
+						parent,
+						&location,
+						vec![],
+						&method.annotations,
+					);
+				}
+				Matched::Both(old, new) => {
+					let location = Location::node(parent).child(&new.name, format!("@{}", new.ordinal));
+					let at = self.changes.len();
+
+					let mut details = vec![];
+					compare(&mut details, "name", &old.name, &new.name);
+					compare(
+						&mut details,
+						"implicit_parameters",
+						&old.implicit_parameters,
+						&new.implicit_parameters,
+					);
+					compare_types(
+						&mut details,
+						"params",
+						Some(&old.params.type_),
+						Some(&new.params.type_),
+					);
+					compare_types(
+						&mut details,
+						"results",
+						Some(&old.results.type_),
+						Some(&new.results.type_),
+					);
+					compare(&mut details, "doc", &old.doc, &new.doc);
+					compare_annotations(&mut details, &old.annotations, &new.annotations);
+
+					// inline param lists are generated structs, so their fields count as the method's
+					for (side, old_list, new_list) in [
+						("params", &old.params, &new.params),
+						("results", &old.results, &new.results),
+					] {
+						match (&old_list.fields, &new_list.fields) {
+							(Some(old_fields), Some(new_fields)) => {
+								self.fields(parent, &location.child(side, side), old_fields, new_fields);
+							}
+							(old_fields, new_fields) => {
+								compare(
+									&mut details,
+									&format!("{side}.inline"),
+									&old_fields.is_some(),
+									&new_fields.is_some(),
+								);
+							}
+						}
+					}
+
+					if !details.is_empty() {
+						self.member(
+							ChangeKind::Changed,
+							Item::Method,
+							parent,
+							&location,
+							details,
+							&new.annotations,
+						);
+						let change = self.changes.pop().unwrap();
+						self.changes.insert(at, change);
+					}
+				}
+			}
+		}
+	}
+
+	fn consts(&mut self, old: &[Const], new: &[Const]) {
+		for pair in matched(old, new, |node| node.declaration.id) {
+			match pair {
+				Matched::Removed(node) => {
+					self.node(ChangeKind::Removed, Item::Const, &node.declaration, vec![])
+				}
+				Matched::Added(node) => self.node(ChangeKind::Added, Item::Const, &node.declaration, vec![]),
+				Matched::Both(old, new) => {
+					let mut details = vec![];
+					compare_declarations(&mut details, &old.declaration, &new.declaration);
+					compare_types(&mut details, "type", Some(&old.type_), Some(&new.type_));
+					compare(&mut details, "value", &old.value, &new.value);
+
+					let at = self.changes.len();
+					self.changed_node(at, Item::Const, &new.declaration, details);
+				}
+			}
+		}
+	}
+
+	fn annotations(&mut self, old: &[Annotation], new: &[Annotation]) {
+		for pair in matched(old, new, |node| node.declaration.id) {
+			match pair {
+				Matched::Removed(node) => {
+					self.node(ChangeKind::Removed, Item::Annotation, &node.declaration, vec![])
+				}
+				Matched::Added(node) => {
+					self.node(ChangeKind::Added, Item::Annotation, &node.declaration, vec![])
+				}
+				Matched::Both(old, new) => {
+					let mut details = vec![];
+					compare_declarations(&mut details, &old.declaration, &new.declaration);
+					compare_types(&mut details, "type", Some(&old.type_), Some(&new.type_));
+					compare(&mut details, "targets", &old.targets, &new.targets);
+
+					let at = self.changes.len();
+					self.changed_node(at, Item::Annotation, &new.declaration, details);
+				}
+			}
+		}
+	}
+}
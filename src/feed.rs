@@ -0,0 +1,210 @@
+use crate::diff::{ChangeKind, Diff};
+use crate::git::{self, Repo, Revision, RevisionArgs};
+use crate::SchemaArgs;
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+#[derive(clap::Args, Debug)]
+pub struct FeedArgs {
+	#[command(flatten)]
+	revisions: RevisionArgs,
+
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	/// Also compare nodes from files that were only pulled in through imports
+	#[arg(long)]
+	include_imports: bool,
+
+	/// Filepath of the Atom feed to append to, created if missing
+	#[arg(short, long, default_value = "./feed.atom")]
+	output: String,
+
+	/// Title for a newly created feed
+	#[arg(long, default_value = "Schema changes")]
+	title: String,
+}
+
+impl FeedArgs {
+	/// Compares each revision with the one before it and adds an entry for every revision
+	/// that changed the schemas. Entries already in the feed are left as they are.
+	pub fn run(self) -> Result<()> {
+		let repo = Repo::open(&self.revisions.repo)?;
+		let walk = git::walk(&repo, &self.revisions, &self.schemas, self.include_imports)?;
+		if walk.len() < 2 {
+			bail!("at least two revisions are needed to compare");
+		}
+
+		let mut entries = vec![];
+
+		// the first revision is only there to be compared against
+		for step in walk.skip(1) {
+			let step = step?;
+			if !step.diff.changes.is_empty() {
+				entries.push(Entry::new(step.revision, &step.diff));
+			}
+		}
+
+		let path = Path::new(&self.output);
+		let feed = match path.exists() {
+			true => fs::read_to_string(path)?,
+			false => empty_feed(&self.title),
+		};
+
+		fs::write(path, append(&feed, entries)?)?;
+
+		Ok(())
+	}
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&apos;")
+}
+
+/// Seconds since the epoch for an RFC 3339 date such as git's `%cI`, which keeps the committer's
+/// offset, so dates from different zones can't be compared as text.
+fn timestamp(date: &str) -> Option<i64> {
+	let number = |range: std::ops::Range<usize>| date.get(range)?.parse::<i64>().ok();
+	let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+	let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+	// fractional seconds are dropped, as git never writes them
+	let zone = date[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+	let offset = match zone {
+		"Z" | "z" => 0,
+		_ => {
+			let sign = match zone.get(..1)? {
+				"+" => 1,
+				"-" => -1,
+				_ => return None,
+			};
+			let hours: i64 = zone.get(1..3)?.parse().ok()?;
+			let minutes: i64 = zone.get(4..6)?.parse().ok()?;
+			sign * (hours * 3600 + minutes * 60)
+		}
+	};
+
+	// days since the epoch, by Howard Hinnant's days_from_civil
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	let days = era * 146097 + day_of_era - 719468;
+
+	Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// 64-bit FNV-1a, which unlike std's hashers is stable across releases.
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+	bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+		(hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+	})
+}
+
+struct Entry {
+	id: String,
+	revision: Revision,
+	title: String,
+	content: String,
+}
+
+impl Entry {
+	fn new(revision: Revision, diff: &Diff) -> Self {
+		// the same revision and changed nodes always give the same ID, so regenerating
+		// the feed never duplicates an entry
+		let nodes: BTreeSet<u64> = diff.changes.iter().map(|change| change.id).collect();
+		let hash = fnv1a(nodes.iter().flat_map(|id| id.to_be_bytes()));
+
+		let count = |kind| diff.changes.iter().filter(|change| change.change == kind).count();
+		let title = format!(
+			"{}: {} added, {} removed, {} changed",
+			revision.name,
+			count(ChangeKind::Added),
+			count(ChangeKind::Removed),
+			count(ChangeKind::Changed)
+		);
+
+		Entry {
+			id: format!("urn:capnp-parse:{}:{hash:016x}", revision.commit),
+			title,
+			content: diff.to_text(),
+			revision,
+		}
+	}
+
+	fn to_xml(&self) -> String {
+		let mut xml = String::from("  <entry>\n");
+		let _ = writeln!(xml, "    <id>{}</id>", escape(&self.id));
+		let _ = writeln!(xml, "    <title>{}</title>", escape(&self.title));
+		let _ = writeln!(xml, "    <updated>{}</updated>", escape(&self.revision.date));
+		let _ = writeln!(xml, "    <summary>{}</summary>", escape(&self.revision.subject));
+		let _ = writeln!(
+			xml,
+			"    <content type=\"text\">{}</content>",
+			escape(&self.content)
+		);
+		xml.push_str("  </entry>\n");
+
+		xml
+	}
+}
+
+fn empty_feed(title: &str) -> String {
+	let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+	xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+	let _ = writeln!(
+		xml,
+		"  <id>urn:capnp-parse:feed:{:016x}</id>",
+		fnv1a(title.bytes())
+	);
+	let _ = writeln!(xml, "  <title>{}</title>", escape(title));
+	xml.push_str("  <updated>1970-01-01T00:00:00Z</updated>\n");
+	xml.push_str("  <author><name>capnp-parse</name></author>\n");
+	xml.push_str("</feed>\n");
+
+	xml
+}
+
+/// Inserts the entries the feed doesn't have yet ahead of its existing ones, newest first,
+/// and moves the feed's `updated` to the newest entry.
+fn append(feed: &str, entries: Vec<Entry>) -> Result<String> {
+	let entries: Vec<Entry> = entries
+		.into_iter()
+		.filter(|entry| !feed.contains(&format!("<id>{}</id>", escape(&entry.id))))
+		.collect();
+
+	let Some(newest) = entries.iter().max_by_key(|entry| timestamp(&entry.revision.date)) else {
+		return Ok(feed.to_string());
+	};
+
+	// entries go ahead of the first existing one, at the start of its line if it has one to itself
+	let Some(insert_at) = feed.find("<entry").or_else(|| feed.find("</feed>")) else {
+		bail!("the existing feed has no closing </feed>");
+	};
+	let insert_at = feed[..insert_at].trim_end_matches([' ', '\t']).len();
+
+	let mut header = feed[..insert_at].to_string();
+	if let (Some(start), Some(end)) = (header.find("<updated>"), header.find("</updated>")) {
+		// backfilling older revisions shouldn't move the feed back in time
+		let start = start + "<updated>".len();
+		if timestamp(&header[start..end]) < timestamp(&newest.revision.date) {
+			header.replace_range(start..end, &escape(&newest.revision.date));
+		}
+	}
+
+	let mut xml = header;
+	for entry in entries.iter().rev() {
+		xml.push_str(&entry.to_xml());
+	}
+	xml.push_str(&feed[insert_at..]);
+
+	Ok(xml)
+}
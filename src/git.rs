@@ -0,0 +1,367 @@
+use crate::diff::{Diff, Format};
+use crate::{extract, Options, Results, SchemaArgs};
+use anyhow::{anyhow, bail, Result};
+use capnpc::codegen::GeneratorContext;
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+
+#[derive(clap::Args, Debug)]
+pub struct GitDiffArgs {
+	/// The older revision: a commit, tag or branch
+	rev_a: String,
+
+	/// The newer revision
+	rev_b: String,
+
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	/// Path to the local git repository
+	#[arg(long, default_value = ".")]
+	repo: String,
+
+	/// Also compare nodes from files that were only pulled in through imports
+	#[arg(long)]
+	include_imports: bool,
+
+	/// Format of the emitted diff
+	#[arg(short, long, value_enum, default_value_t = Format::Text)]
+	format: Format,
+
+	/// Filepath for the diff, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+impl GitDiffArgs {
+	pub fn run(self) -> Result<()> {
+		let repo = Repo::open(&self.repo)?;
+		let old = repo.results(&self.rev_a, &self.schemas, self.include_imports)?;
+		let new = repo.results(&self.rev_b, &self.schemas, self.include_imports)?;
+
+		let rendered = Diff::new(&old, &new).render(self.format)?;
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => print!("{rendered}"),
+		}
+
+		Ok(())
+	}
+}
+
+/// The revisions to walk, oldest first.
+#[derive(clap::Args, Debug)]
+pub struct RevisionArgs {
+	/// A commit range like `v1.0..main`, or an ordered list of revisions. A range like `v1.0...main`
+	/// starts from where the two forked, and an empty side of either is `HEAD`
+	#[arg(required_unless_present = "tags")]
+	revisions: Vec<String>,
+
+	/// Walk every tag in the order they were created, instead of listing revisions
+	#[arg(long, conflicts_with = "revisions")]
+	tags: bool,
+
+	/// Path to the local git repository
+	#[arg(long, default_value = ".")]
+	pub repo: String,
+}
+
+/// A revision as it appears in timelines.
+#[derive(Serialize, Clone, Debug)]
+pub struct Revision {
+	/// The tag or revision it was listed as, or the abbreviated hash for commits in a range.
+	pub name: String,
+	pub commit: String,
+	/// Commit date, in strict ISO 8601.
+	pub date: String,
+	pub subject: String,
+}
+
+impl RevisionArgs {
+	/// Lists the revisions to walk. A range starts with its base, so the first change
+	/// in the range has something to be compared against, and skips commits that
+	/// don't touch any schema.
+	pub fn list(&self, repo: &Repo) -> Result<Vec<Revision>> {
+		let names = match (self.tags, self.revisions.as_slice()) {
+			(true, _) => lines(repo.git(&["tag", "--sort=creatordate"])?)?,
+			(false, [range]) if range.contains("..") => {
+				let or_head = |rev: &str| match rev {
+					"" => "HEAD".to_string(),
+					rev => rev.to_string(),
+				};
+
+				let (base, tip) = match range.split_once("...") {
+					Some((a, b)) => {
+						let base = repo.git(&["merge-base", &or_head(a), &or_head(b)])?;
+						(String::from_utf8(base)?.trim().to_string(), or_head(b))
+					}
+					None => {
+						let (a, b) = range.split_once("..").unwrap_or_default();
+						(or_head(a), or_head(b))
+					}
+				};
+
+				let mut commits = vec![base.clone()];
+				commits.extend(lines(repo.git(&[
+					"rev-list",
+					"--reverse",
+					"--topo-order",
+					&format!("{base}..{tip}"),
+					"--",
+					"*.capnp",
+				])?)?);
+
+				commits
+			}
+			(false, revisions) => revisions.to_vec(),
+		};
+
+		let mut revisions = vec![];
+		for name in names {
+			let commit = repo.resolve(&name)?;
+			let show = repo.git(&["show", "-s", "--format=%cI%x00%s", &commit])?;
+			let show = String::from_utf8(show)?;
+			let (date, subject) = show.trim_end().split_once('\0').unwrap_or_default();
+
+			// commits listed by a range are only known by their hash
+			let name = match name == commit {
+				true => commit[..12].to_string(),
+				false => name,
+			};
+
+			revisions.push(Revision {
+				name,
+				commit,
+				date: date.to_string(),
+				subject: subject.to_string(),
+			});
+		}
+
+		Ok(revisions)
+	}
+}
+
+/// One revision of a walk, with what changed since the revision before it.
+pub struct Step {
+	pub revision: Revision,
+	/// Shared with the walk, which keeps it to compare the next revision against.
+	pub results: Rc<Results>,
+	/// Compared against nothing for the first revision, so everything in it is added.
+	pub diff: Diff,
+}
+
+/// Extracts each revision in turn and compares it with the previous one.
+pub struct Walk<'a> {
+	repo: &'a Repo,
+	schemas: &'a SchemaArgs,
+	include_imports: bool,
+	revisions: std::vec::IntoIter<Revision>,
+	previous: Rc<Results>,
+}
+
+/// Walks the revisions listed by `revisions`, oldest first.
+pub fn walk<'a>(
+	repo: &'a Repo,
+	revisions: &RevisionArgs,
+	schemas: &'a SchemaArgs,
+	include_imports: bool,
+) -> Result<Walk<'a>> {
+	let revisions = revisions.list(repo)?;
+	if revisions.is_empty() {
+		bail!("no revisions to walk");
+	}
+
+	Ok(Walk {
+		repo,
+		schemas,
+		include_imports,
+		revisions: revisions.into_iter(),
+		previous: Rc::default(),
+	})
+}
+
+impl Iterator for Walk<'_> {
+	type Item = Result<Step>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let revision = self.revisions.next()?;
+		eprintln!("extracting {} ({})", revision.name, &revision.commit[..12]);
+
+		let results = match self.repo.results(&revision.commit, self.schemas, self.include_imports) {
+			Ok(results) => results,
+			Err(err) => return Some(Err(err)),
+		};
+		let diff = Diff::new(&self.previous, &results);
+		self.previous = Rc::new(results);
+
+		Some(Ok(Step {
+			revision,
+			results: Rc::clone(&self.previous),
+			diff,
+		}))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.revisions.size_hint()
+	}
+}
+
+impl ExactSizeIterator for Walk<'_> {}
+
+fn lines(output: Vec<u8>) -> Result<Vec<String>> {
+	Ok(String::from_utf8(output)?
+		.lines()
+		.filter(|line| !line.is_empty())
+		.map(str::to_string)
+		.collect())
+}
+
+/// A local repository, only ever read through the `git` command line so nothing is fetched.
+pub struct Repo {
+	path: PathBuf,
+}
+
+impl Repo {
+	pub fn open(path: &str) -> Result<Self> {
+		let repo = Repo {
+			path: PathBuf::from(path),
+		};
+		repo.git(&["rev-parse", "--git-dir"])
+			.map_err(|err| anyhow!("{path} is not a git repository: {err}"))?;
+
+		Ok(repo)
+	}
+
+	/// Runs git in the repository and returns its stdout.
+	pub fn git(&self, args: &[&str]) -> Result<Vec<u8>> {
+		self.git_with_input(args, vec![])
+	}
+
+	/// Runs git in the repository with `input` on its stdin, and returns its stdout.
+	fn git_with_input(&self, args: &[&str], input: Vec<u8>) -> Result<Vec<u8>> {
+		let mut child = Command::new("git")
+			.arg("-C")
+			.arg(&self.path)
+			.args(args)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()?;
+
+		// written from another thread, so git can't stall on a full stdout while we block on its stdin
+		let mut stdin = child.stdin.take().expect("stdin is piped");
+		let writer = thread::spawn(move || stdin.write_all(&input));
+		let output = child.wait_with_output()?;
+
+		if !output.status.success() {
+			bail!(
+				"git {} failed: {}",
+				args.join(" "),
+				String::from_utf8_lossy(&output.stderr).trim()
+			);
+		}
+		writer
+			.join()
+			.map_err(|_| anyhow!("writing to git {} panicked", args.join(" ")))??;
+
+		Ok(output.stdout)
+	}
+
+	/// Resolves a tag, branch or abbreviated hash to the full hash of its commit.
+	pub fn resolve(&self, rev: &str) -> Result<String> {
+		let commit = self.git(&["rev-parse", "--verify", &format!("{rev}^{{commit}}")])?;
+
+		Ok(String::from_utf8(commit)?.trim().to_string())
+	}
+
+	/// Writes every `.capnp` file in the tree of `commit` below `dir`, so imports between
+	/// schemas resolve the same way they did at that commit. The files are read through a
+	/// single `git cat-file --batch`, however many there are.
+	fn export(&self, commit: &str, dir: &Path) -> Result<()> {
+		let listing = self.git(&["ls-tree", "-r", "-z", commit])?;
+
+		// each entry is `<mode> <type> <hash>\t<path>`
+		let mut blobs = vec![];
+		for entry in listing.split(|byte| *byte == 0) {
+			let entry = String::from_utf8_lossy(entry);
+			let Some((info, path)) = entry.split_once('\t') else {
+				continue;
+			};
+			if let [_, "blob", hash] = info.split(' ').collect::<Vec<_>>()[..] {
+				if path.ends_with(".capnp") {
+					blobs.push((hash.to_string(), path.to_string()));
+				}
+			}
+		}
+
+		let input: String = blobs.iter().map(|(hash, _)| format!("{hash}\n")).collect();
+		let output = self.git_with_input(&["cat-file", "--batch"], input.into_bytes())?;
+
+		// and each blob comes back as `<hash> blob <size>\n<contents>\n`
+		let mut rest = output.as_slice();
+		for (_, path) in blobs {
+			let truncated = || anyhow!("git cat-file stopped before {path}");
+			let newline = rest
+				.iter()
+				.position(|byte| *byte == b'\n')
+				.ok_or_else(truncated)?;
+			let header = String::from_utf8_lossy(&rest[..newline]);
+			let size: usize = header.rsplit(' ').next().unwrap_or_default().parse()?;
+			let contents = rest.get(newline + 1..newline + 1 + size).ok_or_else(truncated)?;
+
+			let target = dir.join(&path);
+			if let Some(parent) = target.parent() {
+				fs::create_dir_all(parent)?;
+			}
+			fs::write(target, contents)?;
+
+			rest = rest.get(newline + 2 + size..).unwrap_or_default();
+		}
+
+		Ok(())
+	}
+
+	/// Compiles the schemas matched at `rev` and extracts them into the flat layout.
+	pub fn results(&self, rev: &str, schemas: &SchemaArgs, include_imports: bool) -> Result<Results> {
+		let commit = self.resolve(rev)?;
+		let scratch = Scratch::new(&commit)?;
+		self.export(&commit, &scratch.0)?;
+
+		let message = schemas.compile_in(&scratch.0)?;
+		let gen = GeneratorContext::new(&message)?;
+		let options = Options {
+			include_imports,
+			flatten_inherited: false,
+			verbose: false,
+		};
+
+		extract(&gen, &options)
+	}
+}
+
+/// A temporary directory holding one exported revision, removed once dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+	fn new(commit: &str) -> Result<Self> {
+		let dir = std::env::temp_dir().join(format!("capnp-parse-{}-{commit}", std::process::id()));
+		if dir.exists() {
+			fs::remove_dir_all(&dir)?;
+		}
+		fs::create_dir_all(&dir)?;
+
+		Ok(Scratch(dir))
+	}
+}
+
+impl Drop for Scratch {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
@@ -0,0 +1,225 @@
+use crate::SchemaArgs;
+use anyhow::Result;
+use capnpc::codegen::GeneratorContext;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct GraphArgs {
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	/// Format of the emitted graph
+	#[arg(short, long, value_enum, default_value_t = Format::Json)]
+	format: Format,
+
+	/// Filepath for the graph, instead of stdout
+	#[arg(short, long)]
+	output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+	/// Adjacency lists plus the cycle and unimported-file reports
+	Json,
+	/// GraphViz DOT
+	Dot,
+	/// A Mermaid flowchart
+	Mermaid,
+}
+
+impl GraphArgs {
+	pub fn run(self) -> Result<()> {
+		let message = self.schemas.compile()?;
+		let gen = GeneratorContext::new(&message)?;
+		let graph = ImportGraph::new(&gen)?;
+
+		for cycle in &graph.cycles {
+			eprintln!("import cycle: {}", cycle.join(" -> "));
+		}
+		for file in &graph.unimported {
+			eprintln!("not imported by any file: {file}");
+		}
+
+		let rendered = match self.format {
+			Format::Json => serde_json::to_string_pretty(&graph)?,
+			Format::Dot => graph.to_dot(),
+			Format::Mermaid => graph.to_mermaid(),
+		};
+
+		match self.output {
+			Some(path) => fs::write(path, rendered)?,
+			None => println!("{rendered}"),
+		}
+
+		Ok(())
+	}
+}
+
+/// File-level import edges between the compiled schemas, keyed by file display name.
+#[derive(Serialize)]
+struct ImportGraph {
+	imports: BTreeMap<String, BTreeSet<String>>,
+	/// Groups of files that import each other, directly or transitively.
+	cycles: Vec<Vec<String>>,
+	/// Requested files that no other file imports.
+	unimported: Vec<String>,
+}
+
+impl ImportGraph {
+	fn new(gen: &GeneratorContext) -> Result<Self> {
+		// imports are named the way the source wrote them, so prefer the file node's name
+		let file_name = |id: u64, fallback: &str| -> Result<String> {
+			match gen.node_map.get(&id) {
+				Some(node) => Ok(node.get_display_name()?.to_string()),
+				None => Ok(fallback.to_string()),
+			}
+		};
+
+		let mut imports: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+		let mut requested = vec![];
+
+		for file in gen.request.get_requested_files()?.iter() {
+			let name = file_name(file.get_id(), file.get_filename()?)?;
+
+			let mut targets = BTreeSet::new();
+			for import in file.get_imports()?.iter() {
+				targets.insert(file_name(import.get_id(), import.get_name()?)?);
+			}
+
+			requested.push(name.clone());
+			imports.insert(name, targets);
+		}
+
+		// files that were only imported show up as leaves
+		let leaves: Vec<String> = imports.values().flatten().cloned().collect();
+		for leaf in leaves {
+			imports.entry(leaf).or_default();
+		}
+
+		let imported: BTreeSet<&String> = imports.values().flatten().collect();
+		let mut unimported: Vec<String> = requested
+			.iter()
+			.filter(|file| !imported.contains(file))
+			.cloned()
+			.collect();
+		unimported.sort();
+
+		Ok(ImportGraph {
+			cycles: cycles(&imports),
+			imports,
+			unimported,
+		})
+	}
+
+	fn to_dot(&self) -> String {
+		let quote = |name: &str| format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""));
+		let mut dot = String::from("digraph imports {\n");
+
+		for (file, targets) in &self.imports {
+			if targets.is_empty() {
+				let _ = writeln!(dot, "\t{};", quote(file));
+			}
+
+			for target in targets {
+				let _ = writeln!(dot, "\t{} -> {};", quote(file), quote(target));
+			}
+		}
+
+		dot.push_str("}\n");
+		dot
+	}
+
+	fn to_mermaid(&self) -> String {
+		// mermaid ids can't contain most punctuation, so files are numbered and labelled
+		let ids: HashMap<&String, usize> = self
+			.imports
+			.keys()
+			.enumerate()
+			.map(|(i, file)| (file, i))
+			.collect();
+		let mut mermaid = String::from("graph LR\n");
+
+		for (i, file) in self.imports.keys().enumerate() {
+			let _ = writeln!(mermaid, "\tn{i}[\"{}\"]", file.replace('"', "#quot;"));
+		}
+
+		for (file, targets) in &self.imports {
+			for target in targets {
+				let _ = writeln!(mermaid, "\tn{} --> n{}", ids[file], ids[target]);
+			}
+		}
+
+		mermaid
+	}
+}
+
+/// Finds the strongly connected components of the import graph that form a cycle,
+/// using Tarjan's algorithm.
+fn cycles(graph: &BTreeMap<String, BTreeSet<String>>) -> Vec<Vec<String>> {
+	struct State<'a> {
+		graph: &'a BTreeMap<String, BTreeSet<String>>,
+		next: usize,
+		index: HashMap<&'a str, usize>,
+		low: HashMap<&'a str, usize>,
+		stack: Vec<&'a str>,
+		components: Vec<Vec<String>>,
+	}
+
+	fn connect<'a>(state: &mut State<'a>, file: &'a str) {
+		state.index.insert(file, state.next);
+		state.low.insert(file, state.next);
+		state.next += 1;
+		state.stack.push(file);
+
+		for target in &state.graph[file] {
+			let target = target.as_str();
+
+			if !state.index.contains_key(target) {
+				connect(state, target);
+				let low = state.low[file].min(state.low[target]);
+				state.low.insert(file, low);
+			} else if state.stack.contains(&target) {
+				let low = state.low[file].min(state.index[target]);
+				state.low.insert(file, low);
+			}
+		}
+
+		if state.low[file] == state.index[file] {
+			let mut component = vec![];
+			while let Some(member) = state.stack.pop() {
+				component.push(member.to_string());
+				if member == file {
+					break;
+				}
+			}
+
+			let self_import = state.graph[file].contains(file);
+			if component.len() > 1 || self_import {
+				component.sort();
+				state.components.push(component);
+			}
+		}
+	}
+
+	let mut state = State {
+		graph,
+		next: 0,
+		index: HashMap::new(),
+		low: HashMap::new(),
+		stack: vec![],
+		components: vec![],
+	};
+
+	for file in graph.keys() {
+		if !state.index.contains_key(file.as_str()) {
+			connect(&mut state, file);
+		}
+	}
+
+	state.components.sort();
+	state.components
+}
@@ -0,0 +1,172 @@
+use crate::diff::{ChangeKind, Detail, Diff, Item};
+use crate::git::{self, Repo, Revision, RevisionArgs};
+use crate::{hex_id, SchemaArgs};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+	#[command(flatten)]
+	revisions: RevisionArgs,
+
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	/// Also track nodes from files that were only pulled in through imports
+	#[arg(long)]
+	include_imports: bool,
+
+	/// Filepath for the per-symbol history JSON
+	#[arg(short, long, default_value = "./history.json")]
+	output: String,
+
+	/// Filepath for the Markdown report
+	#[arg(long, default_value = "./history.md")]
+	report: String,
+}
+
+impl HistoryArgs {
+	pub fn run(self) -> Result<()> {
+		let repo = Repo::open(&self.revisions.repo)?;
+		let mut history = History::default();
+
+		for step in git::walk(&repo, &self.revisions, &self.schemas, self.include_imports)? {
+			let step = step?;
+			history.record(step.revision, step.diff);
+		}
+
+		fs::write(self.output, serde_json::to_string_pretty(&history)?)?;
+		fs::write(self.report, history.to_markdown())?;
+
+		Ok(())
+	}
+}
+
+/// Something that happened to a symbol at one revision.
+#[derive(Serialize)]
+pub struct Event {
+	pub revision: String,
+	pub commit: String,
+	pub date: String,
+	pub change: ChangeKind,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub details: Vec<Detail>,
+}
+
+#[derive(Serialize)]
+pub struct Symbol {
+	/// The symbol's name as of its latest event, e.g. `foo.capnp:Foo.bar`.
+	pub name: String,
+	pub item: Item,
+	pub file: String,
+	/// The node that declares the symbol.
+	#[serde(with = "hex_id")]
+	pub id: u64,
+	pub events: Vec<Event>,
+}
+
+/// When each symbol was introduced, changed or removed across a run of revisions. The
+/// first revision is compared against nothing, so everything in it counts as introduced.
+#[derive(Serialize, Default)]
+pub struct History {
+	pub revisions: Vec<Revision>,
+	/// Keyed by the ID of the symbol's node, followed for members by their ordinal path, e.g.
+	/// `0xa93fc509624c72d9.@2`, so a symbol keeps its history when it is renamed.
+	pub symbols: BTreeMap<String, Symbol>,
+	#[serde(skip)]
+	diffs: Vec<Diff>,
+}
+
+impl History {
+	/// Adds the changes `diff` found between the previous revision and `revision`.
+	pub fn record(&mut self, revision: Revision, diff: Diff) {
+		for change in &diff.changes {
+			let key = match change.member.as_str() {
+				"" => format!("{:#018x}", change.id),
+				member => format!("{:#018x}.{member}", change.id),
+			};
+
+			let symbol = self.symbols.entry(key).or_insert_with(|| Symbol {
+				name: change.name.clone(),
+				item: change.item,
+				file: change.file.clone(),
+				id: change.id,
+				events: vec![],
+			});
+
+			symbol.name = change.name.clone();
+			symbol.file = change.file.clone();
+			symbol.events.push(Event {
+				revision: revision.name.clone(),
+				commit: revision.commit.clone(),
+				date: revision.date.clone(),
+				change: change.change,
+				details: change.details.clone(),
+			});
+		}
+
+		self.revisions.push(revision);
+		self.diffs.push(diff);
+	}
+
+	pub fn to_markdown(&self) -> String {
+		let mut markdown = String::from("# Schema history\n\n");
+
+		markdown.push_str("| Revision | Date | Added | Removed | Changed |\n");
+		markdown.push_str("| --- | --- | --- | --- | --- |\n");
+		for (revision, diff) in self.revisions.iter().zip(&self.diffs) {
+			let count = |kind| diff.changes.iter().filter(|change| change.change == kind).count();
+			let _ = writeln!(
+				markdown,
+				"| {} | {} | {} | {} | {} |",
+				revision.name,
+				revision.date,
+				count(ChangeKind::Added),
+				count(ChangeKind::Removed),
+				count(ChangeKind::Changed),
+			);
+		}
+
+		for (i, (revision, diff)) in self.revisions.iter().zip(&self.diffs).enumerate() {
+			let _ = writeln!(markdown, "\n## {}\n", revision.name);
+			let _ = writeln!(
+				markdown,
+				"`{}` · {} · {}\n",
+				&revision.commit[..12],
+				revision.date,
+				revision.subject
+			);
+
+			// listing the whole schema for the baseline would drown out everything else
+			if i == 0 {
+				let _ = writeln!(markdown, "Baseline with {} symbols.", diff.changes.len());
+				continue;
+			}
+
+			if diff.changes.is_empty() {
+				markdown.push_str("No schema changes.\n");
+				continue;
+			}
+
+			for change in &diff.changes {
+				let verb = match change.change {
+					ChangeKind::Added => "Added",
+					ChangeKind::Removed => "Removed",
+					ChangeKind::Changed => "Changed",
+				};
+
+				let _ = write!(markdown, "- {verb} {} `{}`", change.item, change.name);
+				if !change.details.is_empty() {
+					let details: Vec<String> = change.details.iter().map(ToString::to_string).collect();
+					let _ = write!(markdown, ": {}", details.join("; "));
+				}
+				markdown.push('\n');
+			}
+		}
+
+		markdown
+	}
+}
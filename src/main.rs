@@ -1,124 +1,913 @@
-use anyhow::Result;
-use capnp::serialize;
+use anyhow::{anyhow, bail, Result};
+use capnp::message;
+use capnp::serialize::{self, OwnedSegments};
 use capnpc::codegen::GeneratorContext;
 use capnpc::schema_capnp::node::WhichReader;
 use capnpc::schema_capnp::value;
 use capnpc::schema_capnp::*;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use glob::glob;
-use serde::{Serialize, Serializer};
-use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
+use std::fmt;
 use std::fs;
+use std::path::{Path, PathBuf};
 
-fn ordered_map<S>(value: &HashMap<String, String>, serializer: S) -> Result<S::Ok, S::Error>
+mod changelog;
+mod compat;
+mod compat_flags;
+mod compat_timeline;
+mod compiler;
+mod decode;
+mod diff;
+mod feed;
+mod git;
+mod graph;
+mod history;
+mod plugin;
+mod tree;
+
+fn ordered_map<S, V>(value: &HashMap<String, V>, serializer: S) -> Result<S::Ok, S::Error>
 where
 	S: Serializer,
+	V: Serialize,
 {
 	let ordered: BTreeMap<_, _> = value.iter().collect();
 	ordered.serialize(serializer)
 }
 
-#[derive(Serialize)]
-struct Field {
-	name: String,
-	#[serde(serialize_with = "ordered_map")]
-	annotations: HashMap<String, String>,
+/// Node IDs are written in hex, the way the capnp tool prints them.
+mod hex_id {
+	use serde::{de, Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S>(value: &u64, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.serialize_str(&format!("{value:#018x}"))
+	}
+
+	pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		let text = String::deserialize(deserializer)?;
+		let digits = text.strip_prefix("0x").unwrap_or(&text);
+
+		u64::from_str_radix(digits, 16).map_err(de::Error::custom)
+	}
 }
 
-impl Field {
-	fn add_annotation(
-		&mut self,
-		annotation: annotation::Reader,
-		annotation_names: &HashMap<u64, String>,
-	) -> Result<()> {
+fn get_node<'a>(gen: &GeneratorContext<'a>, id: u64) -> Result<node::Reader<'a>> {
+	gen.node_map
+		.get(&id)
+		.copied()
+		.ok_or_else(|| anyhow!("node {id:#018x} is missing from the request"))
+}
+
+fn display_name(gen: &GeneratorContext, id: u64) -> Result<String> {
+	Ok(get_node(gen, id)?.get_display_name()?.to_string())
+}
+
+/// Decoded annotation values, keyed by annotation name.
+type Annotations = HashMap<String, serde_json::Value>;
+
+fn read_annotations(
+	annotations: capnp::struct_list::Reader<annotation::Owned>,
+	gen: &GeneratorContext,
+	annotation_names: &HashMap<u64, String>,
+) -> Result<Annotations> {
+	let mut result = HashMap::new();
+
+	for annotation in annotations.iter() {
 		let id = annotation.get_id();
 		let name = annotation_names.get(&id);
 
 		if let Some(actual_name) = name {
 			let content = annotation.get_value()?;
 
-			let value = match content.which()? {
-				value::Void(..) => "true".to_string(),
-				value::Text(txt) => txt?.to_string(),
-				_ => "unhandled type".to_string(),
+			let value = match (content.which()?, get_node(gen, id)?.which()?) {
+				// a void annotation only marks its target
+				(value::Void(()), _) => serde_json::Value::Bool(true),
+				(_, WhichReader::Annotation(reader)) => decode::value(content, reader.get_type()?, gen)?,
+				_ => bail!("annotation {id:#018x} is not declared by an annotation node"),
 			};
 
-			self.annotations.insert(actual_name.to_string(), value);
+			result.insert(actual_name.to_string(), value);
 		}
+	}
 
-		Ok(())
+	Ok(result)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+enum Type {
+	Void,
+	Bool,
+	Int8,
+	Int16,
+	Int32,
+	Int64,
+	UInt8,
+	UInt16,
+	UInt32,
+	UInt64,
+	Float32,
+	Float64,
+	Text,
+	Data,
+	List {
+		element: Box<Type>,
+	},
+	Enum {
+		name: String,
+		#[serde(with = "hex_id")]
+		id: u64,
+		#[serde(default, skip_serializing_if = "Vec::is_empty")]
+		brand: Vec<BrandScope>,
+	},
+	Struct {
+		name: String,
+		#[serde(with = "hex_id")]
+		id: u64,
+		#[serde(default, skip_serializing_if = "Vec::is_empty")]
+		brand: Vec<BrandScope>,
+	},
+	Interface {
+		name: String,
+		#[serde(with = "hex_id")]
+		id: u64,
+		#[serde(default, skip_serializing_if = "Vec::is_empty")]
+		brand: Vec<BrandScope>,
+	},
+	AnyPointer,
+	AnyStruct,
+	AnyList,
+	Capability,
+	/// A generic parameter of an enclosing struct or interface.
+	Parameter {
+		name: String,
+		scope: String,
+		#[serde(with = "hex_id")]
+		scope_id: u64,
+		index: u16,
+	},
+	/// A generic parameter declared on the method that uses it.
+	ImplicitMethodParameter {
+		index: u16,
+	},
+}
+
+impl Type {
+	fn new(reader: type_::Reader, gen: &GeneratorContext) -> Result<Self> {
+		let kind = match reader.which()? {
+			type_::Void(()) => Type::Void,
+			type_::Bool(()) => Type::Bool,
+			type_::Int8(()) => Type::Int8,
+			type_::Int16(()) => Type::Int16,
+			type_::Int32(()) => Type::Int32,
+			type_::Int64(()) => Type::Int64,
+			type_::Uint8(()) => Type::UInt8,
+			type_::Uint16(()) => Type::UInt16,
+			type_::Uint32(()) => Type::UInt32,
+			type_::Uint64(()) => Type::UInt64,
+			type_::Float32(()) => Type::Float32,
+			type_::Float64(()) => Type::Float64,
+			type_::Text(()) => Type::Text,
+			type_::Data(()) => Type::Data,
+			type_::List(list) => Type::List {
+				element: Box::new(Type::new(list.get_element_type()?, gen)?),
+			},
+			type_::Enum(reader) => {
+				let id = reader.get_type_id();
+				Type::Enum {
+					name: display_name(gen, id)?,
+					id,
+					brand: read_brand(reader.get_brand()?, gen)?,
+				}
+			}
+			type_::Struct(reader) => {
+				let id = reader.get_type_id();
+				Type::Struct {
+					name: display_name(gen, id)?,
+					id,
+					brand: read_brand(reader.get_brand()?, gen)?,
+				}
+			}
+			type_::Interface(reader) => {
+				let id = reader.get_type_id();
+				Type::Interface {
+					name: display_name(gen, id)?,
+					id,
+					brand: read_brand(reader.get_brand()?, gen)?,
+				}
+			}
+			type_::AnyPointer(reader) => match reader.which()? {
+				type_::any_pointer::Unconstrained(reader) => match reader.which()? {
+					type_::any_pointer::unconstrained::AnyKind(()) => Type::AnyPointer,
+					type_::any_pointer::unconstrained::Struct(()) => Type::AnyStruct,
+					type_::any_pointer::unconstrained::List(()) => Type::AnyList,
+					type_::any_pointer::unconstrained::Capability(()) => Type::Capability,
+				},
+				type_::any_pointer::Parameter(reader) => {
+					let scope_id = reader.get_scope_id();
+					let index = reader.get_parameter_index();
+					let scope = get_node(gen, scope_id)?;
+
+					let parameters = scope.get_parameters()?;
+					let name = match parameters.len() > u32::from(index) {
+						true => parameters.get(index.into()).get_name()?.to_string(),
+						false => index.to_string(),
+					};
+
+					Type::Parameter {
+						name,
+						scope: scope.get_display_name()?.to_string(),
+						scope_id,
+						index,
+					}
+				}
+				type_::any_pointer::ImplicitMethodParameter(reader) => Type::ImplicitMethodParameter {
+					index: reader.get_parameter_index(),
+				},
+			},
+		};
+
+		Ok(kind)
 	}
 }
 
-#[derive(Serialize)]
-struct Struct {
+/// Renders types the way they are written in a schema, e.g. `List(Map(Text, Foo))`.
+impl fmt::Display for Type {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Type::List { element } => write!(f, "List({element})"),
+			Type::Enum { name, id, brand }
+			| Type::Struct { name, id, brand }
+			| Type::Interface { name, id, brand } => {
+				// display names are prefixed with the file that declares the node
+				let name = name.split_once(':').map_or(name.as_str(), |(_, name)| name);
+				write!(f, "{name}")?;
+
+				if let Some(scope) = brand.iter().find(|scope| scope.scope_id == *id) {
+					let bindings: Vec<String> = scope
+						.bindings
+						.iter()
+						.map(|binding| match &binding.type_ {
+							Some(type_) => type_.to_string(),
+							None => "AnyPointer".to_string(),
+						})
+						.collect();
+
+					write!(f, "({})", bindings.join(", "))?;
+				}
+
+				Ok(())
+			}
+			Type::Parameter { name, .. } => write!(f, "{name}"),
+			Type::ImplicitMethodParameter { index } => write!(f, "ImplicitMethodParameter({index})"),
+			_ => write!(f, "{self:?}"),
+		}
+	}
+}
+
+/// A generic parameter bound to a concrete type, or left unbound when `type` is null.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Binding {
+	parameter: String,
+	#[serde(rename = "type")]
+	type_: Option<Type>,
+}
+
+/// The bindings a brand supplies for the parameters of one generic scope.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct BrandScope {
+	scope: String,
+	#[serde(with = "hex_id")]
+	scope_id: u64,
+	bindings: Vec<Binding>,
+}
+
+fn read_brand(brand: brand::Reader, gen: &GeneratorContext) -> Result<Vec<BrandScope>> {
+	let mut scopes = vec![];
+
+	for scope in brand.get_scopes()?.iter() {
+		// inherited scopes reuse the enclosing brand and add nothing of their own
+		let bindings = match scope.which()? {
+			brand::scope::Bind(bindings) => bindings?,
+			brand::scope::Inherit(()) => continue,
+		};
+
+		let scope_id = scope.get_scope_id();
+		let node = get_node(gen, scope_id)?;
+		let parameters = node.get_parameters()?;
+
+		let mut result = vec![];
+		for (i, binding) in bindings.iter().enumerate() {
+			let parameter = match parameters.len() > i as u32 {
+				true => parameters.get(i as u32).get_name()?.to_string(),
+				false => i.to_string(),
+			};
+
+			let type_ = match binding.which()? {
+				brand::binding::Unbound(()) => None,
+				brand::binding::Type(type_) => Some(Type::new(type_?, gen)?),
+			};
+
+			result.push(Binding { parameter, type_ });
+		}
+
+		scopes.push(BrandScope {
+			scope: node.get_display_name()?.to_string(),
+			scope_id,
+			bindings: result,
+		});
+	}
+
+	Ok(scopes)
+}
+
+/// Doc comments the compiler recorded for nodes and their members.
+struct Docs {
+	nodes: HashMap<u64, String>,
+	members: HashMap<u64, Vec<String>>,
+}
+
+impl Docs {
+	fn new(gen: &GeneratorContext) -> Result<Self> {
+		let mut docs = Docs {
+			nodes: HashMap::new(),
+			members: HashMap::new(),
+		};
+
+		for info in gen.request.get_source_info()?.iter() {
+			let id = info.get_id();
+			docs.nodes.insert(id, info.get_doc_comment()?.to_string());
+
+			// members line up with the node's fields, enumerants or methods
+			let mut members = vec![];
+			for member in info.get_members()?.iter() {
+				members.push(member.get_doc_comment()?.to_string());
+			}
+			docs.members.insert(id, members);
+		}
+
+		Ok(docs)
+	}
+
+	fn node(&self, id: u64) -> Option<String> {
+		self.nodes.get(&id).and_then(|doc| Docs::clean(doc))
+	}
+
+	fn member(&self, id: u64, index: usize) -> Option<String> {
+		self.members
+			.get(&id)
+			.and_then(|members| members.get(index))
+			.and_then(|doc| Docs::clean(doc))
+	}
+
+	fn clean(doc: &str) -> Option<String> {
+		let doc = doc.trim_end();
+		(!doc.is_empty()).then(|| doc.to_string())
+	}
+}
+
+/// The generic parameters a node declares, and whether it sits inside any generic scope.
+#[derive(Serialize, Deserialize)]
+struct Generics {
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	parameters: Vec<String>,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	is_generic: bool,
+}
+
+impl Generics {
+	fn new(node: node::Reader) -> Result<Self> {
+		let mut parameters = vec![];
+		for parameter in node.get_parameters()?.iter() {
+			parameters.push(parameter.get_name()?.to_string());
+		}
+
+		Ok(Generics {
+			parameters,
+			is_generic: node.get_is_generic(),
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct Field {
 	name: String,
-	fields: Vec<Field>,
+	#[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+	type_: Option<Type>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	ordinal: Option<u16>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	code_order: Option<u16>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	offset: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	had_explicit_default: Option<bool>,
+	/// The decoded default of a slot, which primitive fields are stored XORed with.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	default: Option<serde_json::Value>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	discriminant_value: Option<u16>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	group: Option<Group>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	doc: Option<String>,
+	#[serde(serialize_with = "ordered_map")]
+	annotations: Annotations,
 }
 
-impl Struct {
-	fn add_field<T>(&mut self, name: &T)
+impl Field {
+	fn new<T>(name: &T) -> Self
 	where
 		T: ToString + ?Sized,
 	{
-		self.fields.push(Field {
+		Field {
 			name: name.to_string(),
+			type_: None,
+			ordinal: None,
+			code_order: None,
+			offset: None,
+			had_explicit_default: None,
+			default: None,
+			discriminant_value: None,
+			group: None,
+			doc: None,
 			annotations: HashMap::new(),
+		}
+	}
+
+	fn from_reader(
+		field: field::Reader,
+		doc: Option<String>,
+		gen: &GeneratorContext,
+		annotation_names: &HashMap<u64, String>,
+		docs: &Docs,
+	) -> Result<Self> {
+		let mut result = Field::new(field.get_name()?);
+		result.doc = doc;
+
+		result.ordinal = match field.get_ordinal().which()? {
+			field::ordinal::Implicit(()) => None,
+			field::ordinal::Explicit(ordinal) => Some(ordinal),
+		};
+		result.code_order = Some(field.get_code_order());
+
+		let discriminant_value = field.get_discriminant_value();
+		if discriminant_value != field::NO_DISCRIMINANT {
+			result.discriminant_value = Some(discriminant_value);
+		}
+
+		match field.which()? {
+			field::Slot(slot) => {
+				result.type_ = Some(Type::new(slot.get_type()?, gen)?);
+				result.offset = Some(slot.get_offset());
+				result.had_explicit_default = Some(slot.get_had_explicit_default());
+				result.default = match decode::value(slot.get_default_value()?, slot.get_type()?, gen)? {
+					serde_json::Value::Null => None,
+					default => Some(default),
+				};
+			}
+			field::Group(group) => {
+				result.group = Some(Group::new(group.get_type_id(), gen, annotation_names, docs)?);
+			}
+		}
+
+		result.annotations = read_annotations(field.get_annotations()?, gen, annotation_names)?;
+
+		Ok(result)
+	}
+}
+
+/// Location of the discriminant for a struct or group that contains an unnamed union.
+#[derive(PartialEq, Serialize, Deserialize)]
+struct Union {
+	discriminant_offset: u32,
+	discriminant_count: u16,
+}
+
+impl Union {
+	fn new(reader: node::struct_::Reader) -> Option<Self> {
+		let discriminant_count = reader.get_discriminant_count();
+
+		(discriminant_count > 0).then(|| Union {
+			discriminant_offset: reader.get_discriminant_offset(),
+			discriminant_count,
 		})
 	}
 }
 
-#[derive(Serialize)]
-struct Enum {
+/// A group (or named union) nested under the field that declares it.
+#[derive(Serialize, Deserialize)]
+struct Group {
+	#[serde(with = "hex_id")]
+	id: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	union: Option<Union>,
+	fields: Vec<Field>,
+}
+
+impl Group {
+	fn new(
+		id: u64,
+		gen: &GeneratorContext,
+		annotation_names: &HashMap<u64, String>,
+		docs: &Docs,
+	) -> Result<Self> {
+		let reader = match get_node(gen, id)?.which()? {
+			WhichReader::Struct(reader) => reader,
+			_ => bail!("group {id:#018x} is not a struct node"),
+		};
+
+		let mut fields = vec![];
+		for (i, field) in reader.get_fields()?.iter().enumerate() {
+			let doc = docs.member(id, i);
+			fields.push(Field::from_reader(field, doc, gen, annotation_names, docs)?);
+		}
+
+		Ok(Group {
+			id,
+			union: Union::new(reader),
+			fields,
+		})
+	}
+}
+
+/// What every top-level record carries, whatever kind of node it came from.
+#[derive(Serialize, Deserialize)]
+struct Declaration {
 	name: String,
+	#[serde(with = "hex_id")]
+	id: u64,
+	/// The file node that declares this node.
+	file: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	doc: Option<String>,
+	#[serde(serialize_with = "ordered_map")]
+	annotations: Annotations,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Struct {
+	#[serde(flatten)]
+	declaration: Declaration,
+	#[serde(flatten)]
+	generics: Generics,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	union: Option<Union>,
+	fields: Vec<Field>,
+}
+
+impl Struct {
+	fn add_field(
+		&mut self,
+		field: field::Reader,
+		doc: Option<String>,
+		gen: &GeneratorContext,
+		annotation_names: &HashMap<u64, String>,
+		docs: &Docs,
+	) -> Result<()> {
+		self.fields
+			.push(Field::from_reader(field, doc, gen, annotation_names, docs)?);
+
+		Ok(())
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct Enum {
+	#[serde(flatten)]
+	declaration: Declaration,
+	#[serde(flatten)]
+	generics: Generics,
 	enumerants: Vec<Field>,
 }
 
 impl Enum {
-	fn add_enumerant<T>(&mut self, name: &T)
+	fn add_enumerant<T>(&mut self, name: &T, doc: Option<String>)
 	where
 		T: ToString + ?Sized,
 	{
-		self.enumerants.push(Field {
-			name: name.to_string(),
-			annotations: HashMap::new(),
-		})
+		let mut enumerant = Field::new(name);
+		enumerant.doc = doc;
+
+		self.enumerants.push(enumerant)
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct Interface {
-	name: String,
-	methods: Vec<Field>,
+	#[serde(flatten)]
+	declaration: Declaration,
+	#[serde(flatten)]
+	generics: Generics,
+	superclasses: Vec<Superclass>,
+	methods: Vec<Method>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	inherited_methods: Option<Vec<InheritedMethod>>,
 }
 
 impl Interface {
-	fn add_method<T>(&mut self, name: &T)
-	where
-		T: ToString + ?Sized,
-	{
-		self.methods.push(Field {
-			name: name.to_string(),
-			annotations: HashMap::new(),
+	fn add_superclass(&mut self, superclass: superclass::Reader, gen: &GeneratorContext) -> Result<()> {
+		let id = superclass.get_id();
+
+		self.superclasses.push(Superclass {
+			name: display_name(gen, id)?,
+			id,
+			brand: read_brand(superclass.get_brand()?, gen)?,
+		});
+
+		Ok(())
+	}
+
+	/// Collects the methods of every interface this one extends, nearest superclasses first.
+	fn set_inherited_methods(
+		&mut self,
+		reader: node::interface::Reader,
+		gen: &GeneratorContext,
+	) -> Result<()> {
+		let mut inherited = vec![];
+		let mut visited = HashSet::new();
+		let mut pending: VecDeque<u64> = reader.get_superclasses()?.iter().map(|s| s.get_id()).collect();
+
+		while let Some(id) = pending.pop_front() {
+			if !visited.insert(id) {
+				continue;
+			}
+
+			let node = get_node(gen, id)?;
+			let reader = match node.which()? {
+				WhichReader::Interface(reader) => reader,
+				_ => bail!("superclass {id:#018x} is not an interface"),
+			};
+
+			for (ordinal, method) in reader.get_methods()?.iter().enumerate() {
+				inherited.push(InheritedMethod {
+					interface: node.get_display_name()?.to_string(),
+					interface_id: id,
+					name: method.get_name()?.to_string(),
+					ordinal: ordinal as u16,
+				});
+			}
+
+			pending.extend(reader.get_superclasses()?.iter().map(|s| s.get_id()));
+		}
+
+		self.inherited_methods = Some(inherited);
+
+		Ok(())
+	}
+
+	fn add_method(
+		&mut self,
+		ordinal: u16,
+		method: method::Reader,
+		doc: Option<String>,
+		gen: &GeneratorContext,
+		annotation_names: &HashMap<u64, String>,
+		docs: &Docs,
+	) -> Result<()> {
+		let mut implicit_parameters = vec![];
+		for parameter in method.get_implicit_parameters()?.iter() {
+			implicit_parameters.push(parameter.get_name()?.to_string());
+		}
+
+		self.methods.push(Method {
+			name: method.get_name()?.to_string(),
+			ordinal,
+			code_order: method.get_code_order(),
+			implicit_parameters,
+			params: ParamList::new(
+				method.get_param_struct_type(),
+				method.get_param_brand()?,
+				gen,
+				annotation_names,
+				docs,
+			)?,
+			results: ParamList::new(
+				method.get_result_struct_type(),
+				method.get_result_brand()?,
+				gen,
+				annotation_names,
+				docs,
+			)?,
+			doc,
+			annotations: read_annotations(method.get_annotations()?, gen, annotation_names)?,
+		});
+
+		Ok(())
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct Superclass {
+	name: String,
+	#[serde(with = "hex_id")]
+	id: u64,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	brand: Vec<BrandScope>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InheritedMethod {
+	interface: String,
+	#[serde(with = "hex_id")]
+	interface_id: u64,
+	name: String,
+	ordinal: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Method {
+	name: String,
+	ordinal: u16,
+	code_order: u16,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	implicit_parameters: Vec<String>,
+	params: ParamList,
+	results: ParamList,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	doc: Option<String>,
+	#[serde(serialize_with = "ordered_map")]
+	annotations: Annotations,
+}
+
+/// The struct carrying a method's params or results. Structs the compiler generated for an
+/// inline parameter list have no scope of their own, so their fields are listed here instead.
+#[derive(Serialize, Deserialize)]
+struct ParamList {
+	#[serde(rename = "type")]
+	type_: Type,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	fields: Option<Vec<Field>>,
+}
+
+impl ParamList {
+	fn new(
+		id: u64,
+		brand: brand::Reader,
+		gen: &GeneratorContext,
+		annotation_names: &HashMap<u64, String>,
+		docs: &Docs,
+	) -> Result<Self> {
+		let node = get_node(gen, id)?;
+
+		let fields = match node.which()? {
+			WhichReader::Struct(reader) if node.get_scope_id() == 0 => {
+				let mut fields = vec![];
+				for (i, field) in reader.get_fields()?.iter().enumerate() {
+					let doc = docs.member(id, i);
+					fields.push(Field::from_reader(field, doc, gen, annotation_names, docs)?);
+				}
+
+				Some(fields)
+			}
+			_ => None,
+		};
+
+		Ok(ParamList {
+			type_: Type::Struct {
+				name: node.get_display_name()?.to_string(),
+				id,
+				brand: read_brand(brand, gen)?,
+			},
+			fields,
 		})
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
+struct File {
+	name: String,
+	#[serde(with = "hex_id")]
+	id: u64,
+	/// Whether the file was passed to the compiler, rather than only imported.
+	requested: bool,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	imports: Vec<Import>,
+	#[serde(serialize_with = "ordered_map")]
+	annotations: Annotations,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Import {
+	name: String,
+	#[serde(with = "hex_id")]
+	id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Const {
+	#[serde(flatten)]
+	declaration: Declaration,
+	#[serde(rename = "type")]
+	type_: Type,
+	value: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Annotation {
+	#[serde(flatten)]
+	declaration: Declaration,
+	#[serde(rename = "type")]
+	type_: Type,
+	targets: Vec<String>,
+	uses: usize,
+}
+
+impl Annotation {
+	fn targets(reader: node::annotation::Reader) -> Vec<String> {
+		let targets = [
+			("file", reader.get_targets_file()),
+			("const", reader.get_targets_const()),
+			("enum", reader.get_targets_enum()),
+			("enumerant", reader.get_targets_enumerant()),
+			("struct", reader.get_targets_struct()),
+			("field", reader.get_targets_field()),
+			("union", reader.get_targets_union()),
+			("group", reader.get_targets_group()),
+			("interface", reader.get_targets_interface()),
+			("method", reader.get_targets_method()),
+			("param", reader.get_targets_param()),
+			("annotation", reader.get_targets_annotation()),
+		];
+
+		targets
+			.into_iter()
+			.filter(|(_, enabled)| *enabled)
+			.map(|(target, _)| target.to_string())
+			.collect()
+	}
+}
+
+/// Counts how often each annotation is applied, across every node and member in the request.
+fn count_annotation_uses(gen: &GeneratorContext) -> Result<HashMap<u64, usize>> {
+	let mut uses = HashMap::new();
+	let mut count = |annotations: capnp::struct_list::Reader<annotation::Owned>| {
+		for annotation in annotations.iter() {
+			*uses.entry(annotation.get_id()).or_insert(0) += 1;
+		}
+	};
+
+	for node in gen.request.get_nodes()?.iter() {
+		count(node.get_annotations()?);
+
+		match node.which()? {
+			WhichReader::Struct(reader) => {
+				for field in reader.get_fields()?.iter() {
+					count(field.get_annotations()?);
+				}
+			}
+			WhichReader::Enum(reader) => {
+				for enumerant in reader.get_enumerants()?.iter() {
+					count(enumerant.get_annotations()?);
+				}
+			}
+			WhichReader::Interface(reader) => {
+				for method in reader.get_methods()?.iter() {
+					count(method.get_annotations()?);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	Ok(uses)
+}
+
+#[derive(Default, Serialize, Deserialize)]
 struct Results {
+	files: Vec<File>,
 	structs: Vec<Struct>,
 	enums: Vec<Enum>,
 	interfaces: Vec<Interface>,
-	unk: Vec<String>,
+	consts: Vec<Const>,
+	annotations: Vec<Annotation>,
 }
 
 impl Results {
-	fn add_struct<T>(&mut self, name: &T)
-	where
-		T: ToString + ?Sized,
-	{
+	/// Reads back an output JSON file written with the flat layout.
+	fn load(path: &str) -> Result<Self> {
+		let json = fs::read_to_string(path)?;
+
+		serde_json::from_str(&json).map_err(|err| anyhow!("{path} is not a flat output JSON file: {err}"))
+	}
+
+	fn add_file(&mut self, file: File) {
+		self.files.push(file)
+	}
+
+	fn add_struct(&mut self, declaration: Declaration, generics: Generics, union: Option<Union>) {
 		self.structs.push(Struct {
-			name: name.to_string(),
+			declaration,
+			generics,
+			union,
 			fields: vec![],
 		})
 	}
@@ -127,12 +916,10 @@ impl Results {
 		self.structs.len() - 1
 	}
 
-	fn add_enum<T>(&mut self, name: &T)
-	where
-		T: ToString + ?Sized,
-	{
+	fn add_enum(&mut self, declaration: Declaration, generics: Generics) {
 		self.enums.push(Enum {
-			name: name.to_string(),
+			declaration,
+			generics,
 			enumerants: vec![],
 		})
 	}
@@ -141,13 +928,13 @@ impl Results {
 		self.enums.len() - 1
 	}
 
-	fn add_interface<T>(&mut self, name: &T)
-	where
-		T: ToString + ?Sized,
-	{
+	fn add_interface(&mut self, declaration: Declaration, generics: Generics) {
 		self.interfaces.push(Interface {
-			name: name.to_string(),
+			declaration,
+			generics,
+			superclasses: vec![],
 			methods: vec![],
+			inherited_methods: None,
 		})
 	}
 
@@ -155,67 +942,300 @@ impl Results {
 		self.interfaces.len() - 1
 	}
 
-	fn add_unk<T>(&mut self, name: &T)
-	where
-		T: ToString + ?Sized,
-	{
-		self.unk.push(name.to_string())
+	fn add_const(&mut self, declaration: Declaration, type_: Type, value: serde_json::Value) {
+		self.consts.push(Const {
+			declaration,
+			type_,
+			value,
+		})
+	}
+
+	fn add_annotation(&mut self, declaration: Declaration, type_: Type, targets: Vec<String>, uses: usize) {
+		self.annotations.push(Annotation {
+			declaration,
+			type_,
+			targets,
+			uses,
+		})
 	}
 }
 
 #[derive(Parser, Debug)]
-#[command(about, long_about = None)]
+#[command(about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-	/// A Glob - must be scoped to .capnp schemas
-	#[arg(short, long, default_value = "./**/*.capnp")]
-	glob: String,
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	#[command(flatten)]
+	schemas: SchemaArgs,
+
+	#[command(flatten)]
+	output: OutputArgs,
+}
 
+// how the extracted nodes are written, shared by the standalone and plugin modes
+#[derive(clap::Args, Debug)]
+struct OutputArgs {
 	/// Filepath for the output JSON
 	#[arg(short, long, default_value = "./output.json")]
 	output: String,
 
+	/// List the methods each interface inherits from its superclasses
+	#[arg(long)]
+	flatten_inherited: bool,
+
+	/// Shape of the output JSON
+	#[arg(long, value_enum, default_value_t = Layout::Flat)]
+	layout: Layout,
+
+	/// Also emit nodes from files that were only pulled in through imports
+	#[arg(long, overrides_with = "exclude_imports")]
+	include_imports: bool,
+
+	/// Only emit nodes from the files matched by the glob, or named to capnp as a plugin (the default)
+	#[arg(long)]
+	exclude_imports: bool,
+}
+
+impl OutputArgs {
+	/// Extracts the nodes from a `CodeGeneratorRequest` and writes them out as JSON.
+	fn write(&self, message: &message::Reader<OwnedSegments>) -> Result<()> {
+		let gen = GeneratorContext::new(message)?;
+		let include_imports = self.include_imports && !self.exclude_imports;
+
+		if self.layout == Layout::Tree {
+			let requested = requested_files(&gen)?.files;
+			let include_file = |id: u64| include_imports || requested.contains(&id);
+			let json = serde_json::to_string_pretty(&tree::build(&gen, &include_file)?)?;
+
+			fs::write(&self.output, json)?;
+			return Ok(());
+		}
+
+		let options = Options {
+			include_imports,
+			flatten_inherited: self.flatten_inherited,
+			verbose: true,
+		};
+		let json = serde_json::to_string_pretty(&extract(&gen, &options)?)?;
+
+		fs::write(&self.output, json)?;
+		Ok(())
+	}
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Build the import graph between the matched schema files
+	Graph(graph::GraphArgs),
+	/// Compare two output JSON files written with the flat layout
+	Diff(diff::DiffArgs),
+	/// Fail when the newer output JSON breaks wire or API compatibility with the older one
+	CheckCompat(compat::CompatArgs),
+	/// Compare the matched schemas between two revisions of a local git repository
+	GitDiff(git::GitDiffArgs),
+	/// Trace when each symbol was introduced, changed or removed across git revisions
+	History(history::HistoryArgs),
+	/// Write Markdown release notes for the changes between two output JSON files
+	Changelog(changelog::ChangelogArgs),
+	/// Append an entry per changed revision to a local Atom feed
+	Feed(feed::FeedArgs),
+	/// Report workerd's compatibility flags and the annotations that configure them
+	CompatFlags(compat_flags::CompatFlagsArgs),
+	/// Trace when each compatibility flag appeared, gained an enable date and left experimental
+	CompatTimeline(compat_timeline::CompatTimelineArgs),
+	/// Read a CodeGeneratorRequest from stdin, as the capnp plugin `capnpc-capnp-parse`
+	Plugin(plugin::PluginArgs),
+}
+
+// the schemas to hand to the capnp compiler, shared by every command
+#[derive(clap::Args, Debug)]
+struct SchemaArgs {
+	/// A Glob - must be scoped to .capnp schemas
+	#[arg(short, long, default_value = "./**/*.capnp")]
+	glob: String,
+
 	/// Filenames to exclude
 	#[arg(short, long)]
 	excludes: Option<Vec<String>>,
+
+	/// Which compiler turns the schemas into nodes
+	#[arg(long, value_enum, default_value_t = Backend::Builtin)]
+	backend: Backend,
+
+	/// The capnp tool for `--backend capnp`, instead of $CAPNP or the first `capnp` on $PATH
+	#[arg(long, value_name = "PATH")]
+	capnp: Option<PathBuf>,
+
+	/// A directory to search for imports starting with `/`; repeatable. Relative paths are
+	/// taken from the root of the glob
+	#[arg(short = 'I', long = "import-path", value_name = "DIR")]
+	import_paths: Vec<PathBuf>,
+
+	/// Don't search /usr/local/include and /usr/include for imports
+	#[arg(long)]
+	no_standard_import: bool,
+
+	/// A prefix to strip from the names of the matched files; repeatable
+	#[arg(long = "src-prefix", value_name = "PREFIX")]
+	src_prefixes: Vec<PathBuf>,
 }
 
-fn main() -> Result<()> {
-	let args = Args::parse();
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+	/// The compiler built into this tool
+	Builtin,
+	/// The capnp tool
+	Capnp,
+}
 
-	let files = glob(&args.glob)?;
+impl SchemaArgs {
+	/// Compiles the matched schemas and reads back the compiler's `CodeGeneratorRequest`.
+	fn compile(&self) -> Result<message::Reader<OwnedSegments>> {
+		self.compile_in(Path::new("."))
+	}
 
-	let mut cmd = std::process::Command::new("/usr/local/bin/capnp");
-	cmd.args(["compile", "-o", "-"]);
+	/// Like `compile`, but with the glob and the compiler both rooted at `dir`, so file
+	/// names come out relative to it.
+	fn compile_in(&self, dir: &Path) -> Result<message::Reader<OwnedSegments>> {
+		let pattern = dir.join(&self.glob);
+		let mut files = vec![];
 
-	for file in files.flatten() {
-		let name = file
-			.file_name()
-			.map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+		for file in glob(&pattern.to_string_lossy())?.flatten() {
+			let name = file
+				.file_name()
+				.map_or_else(String::new, |name| name.to_string_lossy().into_owned());
 
-		if let Some(x) = &args.excludes {
-			if x.contains(&name) {
-				continue;
+			if let Some(x) = &self.excludes {
+				if x.contains(&name) {
+					continue;
+				}
 			}
+
+			files.push(file.strip_prefix(dir).unwrap_or(&file).to_path_buf());
+		}
+
+		if self.backend == Backend::Builtin {
+			let options = compiler::Options {
+				import_paths: self.import_paths.clone(),
+				standard_import: !self.no_standard_import,
+				src_prefixes: self.src_prefixes.clone(),
+			};
+			return compiler::compile(dir, &files, &options);
 		}
 
-		cmd.arg(file.display().to_string());
+		let capnp = self
+			.capnp
+			.clone()
+			.or_else(|| env::var_os("CAPNP").map(PathBuf::from));
+		let capnp = capnp.unwrap_or_else(|| PathBuf::from("capnp"));
+
+		let mut cmd = std::process::Command::new(&capnp);
+		cmd.current_dir(dir);
+		cmd.args(["compile", "-o", "-"]);
+		for path in &self.import_paths {
+			cmd.arg(format!("--import-path={}", path.display()));
+		}
+		if self.no_standard_import {
+			cmd.arg("--no-standard-import");
+		}
+		for prefix in &self.src_prefixes {
+			cmd.arg(format!("--src-prefix={}", prefix.display()));
+		}
+		cmd.args(&files);
+
+		cmd.stdout(std::process::Stdio::piped());
+		let mut output = cmd.spawn().map_err(|err| {
+			anyhow!(
+				"can't run {}: {err}; point --capnp or $CAPNP at it",
+				capnp.display()
+			)
+		})?;
+
+		let message = serialize::read_message(output.stdout.take().unwrap(), message::ReaderOptions::new())?;
+
+		Ok(message)
 	}
+}
 
-	cmd.stdout(std::process::Stdio::piped());
-	let mut output = cmd.spawn()?;
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Layout {
+	/// Separate lists of structs, enums, interfaces, consts and annotations
+	Flat,
+	/// Files and the declarations nested in them, following each node's scope
+	Tree,
+}
 
-	let message = serialize::read_message(
-		output.stdout.take().unwrap(),
-		capnp::message::ReaderOptions::new(),
-	)?;
+/// Walks up the scope chain to the file node that declares `node`.
+fn origin_file<'a>(gen: &GeneratorContext<'a>, node: node::Reader<'a>) -> Result<node::Reader<'a>> {
+	let mut current = node;
 
-	let gen = GeneratorContext::new(&message)?;
+	while !matches!(current.which()?, WhichReader::File(())) {
+		current = get_node(gen, current.get_scope_id())?;
+	}
+
+	Ok(current)
+}
+
+/// Which nodes `extract` emits, and how.
+struct Options {
+	include_imports: bool,
+	flatten_inherited: bool,
+	/// Print each node and member as it is visited.
+	verbose: bool,
+}
+
+/// Files passed to the compiler, and what each of them imports.
+struct Requested {
+	files: HashSet<u64>,
+	imports: HashMap<u64, Vec<Import>>,
+}
+
+fn requested_files(gen: &GeneratorContext) -> Result<Requested> {
+	let mut requested = HashSet::new();
+	let mut imports: HashMap<u64, Vec<Import>> = HashMap::new();
+	for file in gen.request.get_requested_files()?.iter() {
+		let mut file_imports = vec![];
+		for import in file.get_imports()?.iter() {
+			file_imports.push(Import {
+				name: import.get_name()?.to_string(),
+				id: import.get_id(),
+			});
+		}
+
+		requested.insert(file.get_id());
+		imports.insert(file.get_id(), file_imports);
+	}
+
+	Ok(Requested {
+		files: requested,
+		imports,
+	})
+}
+
+/// Walks the compiled nodes into the flat `Results` layout.
+fn extract(gen: &GeneratorContext, options: &Options) -> Result<Results> {
+	macro_rules! progress {
+		($($arg:tt)*) => {
+			if options.verbose {
+				println!($($arg)*);
+			}
+		};
+	}
+
+	let Requested {
+		files: requested,
+		mut imports,
+	} = requested_files(gen)?;
+	let include_file = |id: u64| options.include_imports || requested.contains(&id);
 
 	let mut results = Results {
+		files: vec![],
 		structs: vec![],
 		enums: vec![],
 		interfaces: vec![],
-		unk: vec![],
+		consts: vec![],
+		annotations: vec![],
 	};
 	let mut annotation_names: HashMap<u64, String> = HashMap::new();
 
@@ -231,13 +1251,49 @@ fn main() -> Result<()> {
 		}
 	}
 
+	let annotation_uses = count_annotation_uses(gen)?;
+	let docs = Docs::new(gen)?;
+
 	for node in gen.request.get_nodes()?.iter() {
+		// groups are emitted under the field that declares them, and implicit
+		// param structs under the method that declares them
+		if let WhichReader::Struct(reader) = node.which()? {
+			if reader.get_is_group() || node.get_scope_id() == 0 {
+				continue;
+			}
+		}
+
+		let file = origin_file(gen, node)?;
+		if !include_file(file.get_id()) {
+			continue;
+		}
+
 		let node_name = node.get_display_name()?;
+		let id = node.get_id();
+		let annotations = read_annotations(node.get_annotations()?, gen, &annotation_names)?;
+
+		let declaration = Declaration {
+			name: node_name.to_string(),
+			id,
+			file: file.get_display_name()?.to_string(),
+			doc: docs.node(id),
+			annotations,
+		};
 
 		match node.which()? {
+			WhichReader::File(()) => {
+				progress!("file: {node_name}");
+				results.add_file(File {
+					name: declaration.name,
+					id,
+					requested: requested.contains(&id),
+					imports: imports.remove(&id).unwrap_or_default(),
+					annotations: declaration.annotations,
+				});
+			}
 			WhichReader::Struct(reader) => {
-				println!("struct: {node_name}");
-				results.add_struct(node_name);
+				progress!("struct: {node_name}");
+				results.add_struct(declaration, Generics::new(node)?, Union::new(reader));
 
 				let idx = results.get_current_struct();
 				let fields = reader.get_fields()?;
@@ -245,18 +1301,19 @@ fn main() -> Result<()> {
 				for (i, field) in fields.iter().enumerate() {
 					let field_name = field.get_name()?;
 
-					println!("	field: {field_name}");
-					results.structs[idx].add_field(field_name);
-
-					let annotations = field.get_annotations()?;
-					for annotation in annotations.iter() {
-						results.structs[idx].fields[i].add_annotation(annotation, &annotation_names)?;
-					}
+					progress!("	field: {field_name}");
+					results.structs[idx].add_field(
+						field,
+						docs.member(id, i),
+						gen,
+						&annotation_names,
+						&docs,
+					)?;
 				}
 			}
 			WhichReader::Enum(reader) => {
-				println!("enum: {node_name}");
-				results.add_enum(node_name);
+				progress!("enum: {node_name}");
+				results.add_enum(declaration, Generics::new(node)?);
 
 				let idx = results.get_current_enum();
 				let enumerants = reader.get_enumerants()?;
@@ -264,40 +1321,102 @@ fn main() -> Result<()> {
 				for (i, enumerant) in enumerants.iter().enumerate() {
 					let enumerant_name = enumerant.get_name()?;
 
-					println!("	enumerant: {enumerant_name}");
-					results.enums[idx].add_enumerant(enumerant_name);
+					progress!("	enumerant: {enumerant_name}");
+					results.enums[idx].add_enumerant(enumerant_name, docs.member(id, i));
 
 					let annotations = enumerant.get_annotations()?;
-					for annotation in annotations.iter() {
-						results.enums[idx].enumerants[i].add_annotation(annotation, &annotation_names)?;
-					}
+					results.enums[idx].enumerants[i].annotations =
+						read_annotations(annotations, gen, &annotation_names)?;
 				}
 			}
 			WhichReader::Interface(reader) => {
-				println!("interface: {node_name}");
-				results.add_interface(node_name);
+				progress!("interface: {node_name}");
+				results.add_interface(declaration, Generics::new(node)?);
 
 				let idx = results.get_current_interface();
 				let methods = reader.get_methods()?;
 
-				for (i, method) in methods.iter().enumerate() {
+				for superclass in reader.get_superclasses()?.iter() {
+					results.interfaces[idx].add_superclass(superclass, gen)?;
+				}
+
+				// methods are ordered by ordinal
+				for (ordinal, method) in methods.iter().enumerate() {
 					let method_name = method.get_name()?;
 
-					println!("	method: {method_name}");
-					results.interfaces[idx].add_method(method_name);
+					progress!("	method: {method_name}");
+					results.interfaces[idx].add_method(
+						ordinal as u16,
+						method,
+						docs.member(id, ordinal),
+						gen,
+						&annotation_names,
+						&docs,
+					)?;
+				}
 
-					let annotations = method.get_annotations()?;
-					for annotation in annotations.iter() {
-						results.interfaces[idx].methods[i].add_annotation(annotation, &annotation_names)?;
-					}
+				if options.flatten_inherited {
+					results.interfaces[idx].set_inherited_methods(reader, gen)?;
 				}
 			}
-			_ => results.add_unk(node_name),
+			WhichReader::Const(reader) => {
+				progress!("const: {node_name}");
+
+				let type_ = reader.get_type()?;
+				let value = decode::value(reader.get_value()?, type_, gen)?;
+				results.add_const(declaration, Type::new(type_, gen)?, value);
+			}
+			WhichReader::Annotation(reader) => {
+				progress!("annotation: {node_name}");
+
+				let uses = annotation_uses.get(&id).copied().unwrap_or(0);
+				results.add_annotation(
+					declaration,
+					Type::new(reader.get_type()?, gen)?,
+					Annotation::targets(reader),
+					uses,
+				);
+			}
 		}
 	}
 
-	let json = serde_json::to_string_pretty(&results)?;
+	Ok(results)
+}
+
+/// capnp runs the plugin for `-o name` as `capnpc-name`, with no arguments.
+fn invoked_as_plugin() -> bool {
+	env::args_os()
+		.next()
+		.and_then(|arg| {
+			Path::new(&arg)
+				.file_name()
+				.map(|name| name.to_string_lossy().starts_with("capnpc-"))
+		})
+		.unwrap_or(false)
+}
+
+fn main() -> Result<()> {
+	let args = if invoked_as_plugin() {
+		Args::parse_from(["capnp-parse", "plugin"])
+	} else {
+		Args::parse()
+	};
+
+	if let Some(command) = args.command {
+		return match command {
+			Command::Graph(graph) => graph.run(),
+			Command::Diff(diff) => diff.run(),
+			Command::CheckCompat(compat) => compat.run(),
+			Command::GitDiff(git_diff) => git_diff.run(),
+			Command::History(history) => history.run(),
+			Command::Changelog(changelog) => changelog.run(),
+			Command::Feed(feed) => feed.run(),
+			Command::CompatFlags(compat_flags) => compat_flags.run(),
+			Command::CompatTimeline(compat_timeline) => compat_timeline.run(),
+			Command::Plugin(plugin) => plugin.run(),
+		};
+	}
 
-	fs::write(args.output, json)?;
-	Ok(())
+	let message = args.schemas.compile()?;
+	args.output.write(&message)
 }
@@ -0,0 +1,18 @@
+use crate::OutputArgs;
+use anyhow::Result;
+use capnp::{message, serialize};
+use std::io;
+
+#[derive(clap::Args, Debug)]
+pub struct PluginArgs {
+	#[command(flatten)]
+	output: OutputArgs,
+}
+
+impl PluginArgs {
+	pub fn run(self) -> Result<()> {
+		// capnp pipes the request in, and runs the plugin from inside the output directory
+		let message = serialize::read_message(io::stdin().lock(), message::ReaderOptions::new())?;
+		self.output.write(&message)
+	}
+}
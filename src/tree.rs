@@ -0,0 +1,75 @@
+use crate::hex_id;
+use anyhow::Result;
+use capnpc::codegen::GeneratorContext;
+use capnpc::schema_capnp::node::{self, WhichReader};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A declaration and everything nested inside it, following the schema's scopes.
+#[derive(Serialize)]
+pub struct TreeNode {
+	name: String,
+	display_name: String,
+	kind: &'static str,
+	#[serde(serialize_with = "hex_id::serialize")]
+	id: u64,
+	#[serde(serialize_with = "hex_id::serialize")]
+	scope_id: u64,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+	fn new<'a>(node: node::Reader<'a>, nodes: &HashMap<u64, node::Reader<'a>>) -> Result<Self> {
+		let kind = match node.which()? {
+			WhichReader::File(()) => "file",
+			WhichReader::Struct(_) => "struct",
+			WhichReader::Enum(_) => "enum",
+			WhichReader::Interface(_) => "interface",
+			WhichReader::Const(_) => "const",
+			WhichReader::Annotation(_) => "annotation",
+		};
+
+		let mut children = vec![];
+		for nested in node.get_nested_nodes()?.iter() {
+			// capnp leaves out nodes nothing in the request needs, such as unused parts of imports
+			if let Some(child) = nodes.get(&nested.get_id()) {
+				children.push(TreeNode::new(*child, nodes)?);
+			}
+		}
+
+		let display_name = node.get_display_name()?;
+		let prefix_len = node.get_display_name_prefix_length() as usize;
+
+		Ok(TreeNode {
+			name: display_name[prefix_len..].to_string(),
+			display_name: display_name.to_string(),
+			kind,
+			id: node.get_id(),
+			scope_id: node.get_scope_id(),
+			children,
+		})
+	}
+}
+
+/// Builds one tree per file node, from its top-level declarations down, skipping files
+/// that `include_file` rejects.
+pub fn build(gen: &GeneratorContext, include_file: &dyn Fn(u64) -> bool) -> Result<Vec<TreeNode>> {
+	let nodes: HashMap<_, _> = gen
+		.request
+		.get_nodes()?
+		.iter()
+		.map(|node| (node.get_id(), node))
+		.collect();
+	let mut files = vec![];
+
+	for node in gen.request.get_nodes()?.iter() {
+		if let WhichReader::File(()) = node.which()? {
+			if include_file(node.get_id()) {
+				files.push(TreeNode::new(node, &nodes)?);
+			}
+		}
+	}
+
+	Ok(files)
+}
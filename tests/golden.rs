@@ -0,0 +1,114 @@
+//! Compiles the schemas under `tests/fixtures` and compares the output JSON with the copies under
+//! `tests/golden`. Run with `UPDATE_GOLDEN=1` to rewrite those copies after an intended change.
+
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// capnp's own schema.capnp, whose IDs and offsets are known from the code generated from it.
+const SCHEMA: &str = "schema";
+/// Unions and groups, generics, defaults and consts, and annotations.
+const FEATURES: &str = "features";
+
+fn root() -> &'static Path {
+	Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Runs the tool over every schema in a fixture and returns the output JSON.
+fn compile(fixture: &str, args: &[&str]) -> Value {
+	let output = env::temp_dir().join(format!(
+		"capnp-parse-golden-{}-{fixture}-{}.json",
+		std::process::id(),
+		args.join("")
+	));
+
+	let result = Command::new(env!("CARGO_BIN_EXE_capnp-parse"))
+		.current_dir(root().join("tests/fixtures").join(fixture))
+		.args(args)
+		.arg("--output")
+		.arg(&output)
+		.stdout(Stdio::null())
+		.output()
+		.expect("failed to run capnp-parse");
+	assert!(
+		result.status.success(),
+		"capnp-parse failed on {fixture}: {}",
+		String::from_utf8_lossy(&result.stderr)
+	);
+
+	let json = fs::read_to_string(&output).unwrap();
+	let _ = fs::remove_file(&output);
+	serde_json::from_str(&json).unwrap()
+}
+
+fn golden(fixture: &str) {
+	let actual = serde_json::to_string_pretty(&compile(fixture, &[])).unwrap() + "\n";
+	let path: PathBuf = root().join("tests/golden").join(format!("{fixture}.json"));
+
+	if env::var_os("UPDATE_GOLDEN").is_some() {
+		fs::write(&path, &actual).unwrap();
+		return;
+	}
+
+	let expected = fs::read_to_string(&path).unwrap_or_default();
+	assert!(
+		actual == expected,
+		"{fixture} no longer matches {}; rerun with UPDATE_GOLDEN=1 if that is intended",
+		path.display()
+	);
+}
+
+#[test]
+fn schema_matches_golden() {
+	golden(SCHEMA);
+}
+
+#[test]
+fn features_match_golden() {
+	golden(FEATURES);
+}
+
+/// The capnp tool, found the same way `--backend capnp` looks for it.
+fn capnp() -> String {
+	let capnp = env::var("CAPNP").unwrap_or_else(|_| "capnp".to_string());
+	let found = Command::new(&capnp)
+		.arg("--version")
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.status()
+		.is_ok_and(|status| status.success());
+
+	assert!(found, "can't run {capnp}; point $CAPNP at the capnp tool");
+	capnp
+}
+
+/// The two backends list nodes in their own order, so records are compared by ID.
+fn sorted(mut results: Value) -> Value {
+	if let Value::Object(lists) = &mut results {
+		for list in lists.values_mut() {
+			if let Value::Array(items) = list {
+				items.sort_by_key(|item| item["id"].as_str().unwrap_or_default().to_string());
+			}
+		}
+	}
+
+	results
+}
+
+/// The golden files are written by the built-in compiler, so this is what ties them to capnp's
+/// own output. It needs the capnp tool, which CI doesn't install, so run it with
+/// `cargo test -- --ignored` after changing the compiler or the fixtures.
+#[test]
+#[ignore = "needs the capnp tool, from $CAPNP or $PATH"]
+fn backends_agree() {
+	let capnp = capnp();
+
+	for fixture in [SCHEMA, FEATURES] {
+		let builtin = sorted(compile(fixture, &["--backend", "builtin"]));
+		let tool = sorted(compile(fixture, &["--backend", "capnp", "--capnp", &capnp]));
+
+		assert!(builtin == tool, "the backends disagree on {fixture}");
+	}
+}